@@ -0,0 +1,369 @@
+use super::mem::*;
+
+/// Mask and sign bit for an operand width
+fn width(w: bool) -> (u32, u32) {
+    match w {
+        true => (0xFFFF, 0x8000),
+        false => (0xFF, 0x80),
+    }
+}
+
+/// Sets SF, ZF and PF from a result. PF only looks at the low 8 bits.
+pub fn set_szp(mem: &mut Memory, result: u16, w: bool) {
+    let (mask, sign) = width(w);
+    let result = u32::from(result) & mask;
+
+    mem.set_flag(Flag::SF, result & sign != 0);
+    mem.set_flag(Flag::ZF, result == 0);
+    mem.set_flag(Flag::PF, (result & 0xFF).count_ones() % 2 == 0);
+}
+
+/// ADD (and ADC with `carry`), setting all arithmetic flags
+pub fn add(mem: &mut Memory, a: u16, b: u16, w: bool, carry: bool) -> u16 {
+    let (mask, sign) = width(w);
+    let (a, b) = (u32::from(a) & mask, u32::from(b) & mask);
+    let full = a + b + u32::from(carry);
+    let result = full & mask;
+
+    mem.set_flag(Flag::CF, full > mask);
+    mem.set_flag(Flag::AF, (a ^ b ^ result) & 0x10 != 0);
+    mem.set_flag(Flag::OF, (a ^ result) & (b ^ result) & sign != 0);
+    set_szp(mem, result as u16, w);
+
+    result as u16
+}
+
+/// SUB/CMP (and SBB with `borrow`), setting all arithmetic flags
+pub fn sub(mem: &mut Memory, a: u16, b: u16, w: bool, borrow: bool) -> u16 {
+    let (mask, sign) = width(w);
+    let (a, b) = (u32::from(a) & mask, u32::from(b) & mask);
+    let result = a.wrapping_sub(b).wrapping_sub(u32::from(borrow)) & mask;
+
+    mem.set_flag(Flag::CF, b + u32::from(borrow) > a);
+    mem.set_flag(Flag::AF, (a ^ b ^ result) & 0x10 != 0);
+    mem.set_flag(Flag::OF, (a ^ b) & (a ^ result) & sign != 0);
+    set_szp(mem, result as u16, w);
+
+    result as u16
+}
+
+/// AND/OR/XOR/TEST result flags: CF and OF cleared, SF/ZF/PF from the result
+pub fn logic(mem: &mut Memory, result: u16, w: bool) -> u16 {
+    let (mask, _) = width(w);
+    let result = (u32::from(result) & mask) as u16;
+
+    mem.set_flag(Flag::CF, false);
+    mem.set_flag(Flag::OF, false);
+    mem.set_flag(Flag::AF, false);
+    set_szp(mem, result, w);
+
+    result
+}
+
+/// INC/DEC: ADD/SUB of 1 that leaves CF alone
+pub fn inc_dec(mem: &mut Memory, a: u16, w: bool, inc: bool) -> u16 {
+    let cf = mem.get_flag(Flag::CF);
+    let result = match inc {
+        true => add(mem, a, 1, w, false),
+        false => sub(mem, a, 1, w, false),
+    };
+    mem.set_flag(Flag::CF, cf);
+    result
+}
+
+/// NEG: 0 - a, with CF set unless a is 0
+pub fn neg(mem: &mut Memory, a: u16, w: bool) -> u16 {
+    let result = sub(mem, 0, a, w, false);
+    let (mask, _) = width(w);
+    mem.set_flag(Flag::CF, u32::from(a) & mask != 0);
+    result
+}
+
+/// Shift and rotate operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shift {
+    Shl,
+    Shr,
+    Sar,
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+}
+
+/// Shifts or rotates `a` by `count` bits one at a time, as the 8086 does (the count isn't masked).
+/// OF is only defined for a count of 1 but is set from the last step regardless.
+pub fn shift(mem: &mut Memory, op: Shift, a: u16, count: u8, w: bool) -> u16 {
+    let (mask, sign) = width(w);
+    let mut val = u32::from(a) & mask;
+
+    if count == 0 {
+        return val as u16;
+    }
+
+    let mut cf = mem.get_flag(Flag::CF);
+    let mut prev = val;
+    for _ in 0..count {
+        prev = val;
+        let msb = val & sign != 0;
+        let lsb = val & 1 != 0;
+        val = match op {
+            Shift::Shl => (val << 1) & mask,
+            Shift::Shr => val >> 1,
+            Shift::Sar => (val >> 1) | (val & sign),
+            Shift::Rol => ((val << 1) & mask) | u32::from(msb),
+            Shift::Ror => (val >> 1) | if lsb { sign } else { 0 },
+            Shift::Rcl => ((val << 1) & mask) | u32::from(cf),
+            Shift::Rcr => (val >> 1) | if cf { sign } else { 0 },
+        };
+        cf = match op {
+            Shift::Shl | Shift::Rol | Shift::Rcl => msb,
+            _ => lsb,
+        };
+    }
+
+    let msb = val & sign != 0;
+    let next = val & (sign >> 1) != 0;
+    let of = match op {
+        Shift::Shl | Shift::Rol | Shift::Rcl => msb != cf,
+        Shift::Shr => prev & sign != 0,
+        Shift::Sar => false,
+        Shift::Ror | Shift::Rcr => msb != next,
+    };
+
+    mem.set_flag(Flag::CF, cf);
+    mem.set_flag(Flag::OF, of);
+    if matches!(op, Shift::Shl | Shift::Shr | Shift::Sar) {
+        mem.set_flag(Flag::AF, false);
+        set_szp(mem, val as u16, w);
+    }
+
+    val as u16
+}
+
+/// MUL/IMUL of the accumulator by `b`. Returns the (high, low) halves; CF and OF are set when
+/// the high half holds significant bits.
+pub fn mul(mem: &mut Memory, a: u16, b: u16, w: bool, signed: bool) -> (u16, u16) {
+    let (hi, lo, overflow) = match (w, signed) {
+        (true, false) => {
+            let result = u32::from(a) * u32::from(b);
+            ((result >> 16) as u16, result as u16, result >> 16 != 0)
+        },
+        (true, true) => {
+            let result = i32::from(a as i16) * i32::from(b as i16);
+            ((result >> 16) as u16, result as u16, result != i32::from(result as i16))
+        },
+        (false, false) => {
+            let result = u16::from(a as u8) * u16::from(b as u8);
+            (result >> 8, result & 0xFF, result >> 8 != 0)
+        },
+        (false, true) => {
+            let result = i16::from(a as u8 as i8) * i16::from(b as u8 as i8);
+            ((result as u16) >> 8, result as u16 & 0xFF, result != i16::from(result as i8))
+        },
+    };
+
+    mem.set_flag(Flag::CF, overflow);
+    mem.set_flag(Flag::OF, overflow);
+    (hi, lo)
+}
+
+/// DIV/IDIV of `hi:lo` by `b`. Returns (quotient, remainder), or None on divide error.
+pub fn div(hi: u16, lo: u16, b: u16, w: bool, signed: bool) -> Option<(u16, u16)> {
+    match (w, signed) {
+        (true, false) => {
+            let a = u32::from(hi) << 16 | u32::from(lo);
+            let b = u32::from(b);
+            let quotient = a.checked_div(b)?;
+            u16::try_from(quotient).ok().map(|q| (q, (a % b) as u16))
+        },
+        (true, true) => {
+            let a = (u32::from(hi) << 16 | u32::from(lo)) as i32;
+            let b = i32::from(b as i16);
+            let quotient = a.checked_div(b)?;
+            i16::try_from(quotient).ok().map(|q| (q as u16, (a % b) as u16))
+        },
+        (false, false) => {
+            let b = u16::from(b as u8);
+            let quotient = lo.checked_div(b)?;
+            u8::try_from(quotient).ok().map(|q| (u16::from(q), lo % b))
+        },
+        (false, true) => {
+            let a = lo as i16;
+            let b = i16::from(b as u8 as i8);
+            let quotient = a.checked_div(b)?;
+            i8::try_from(quotient).ok().map(|q| (q as u8 as u16, (a % b) as u8 as u16))
+        },
+    }
+}
+
+/// DAA/DAS decimal adjust of AL after an addition or subtraction
+pub fn decimal_adjust(mem: &mut Memory, al: u8, subtract: bool) -> u8 {
+    let old_cf = mem.get_flag(Flag::CF);
+    let mut al = al;
+    let old_al = al;
+
+    if al & 0x0F > 9 || mem.get_flag(Flag::AF) {
+        al = match subtract {
+            true => al.wrapping_sub(6),
+            false => al.wrapping_add(6),
+        };
+        mem.set_flag(Flag::AF, true);
+    } else {
+        mem.set_flag(Flag::AF, false);
+    }
+
+    if old_al > 0x99 || old_cf {
+        al = match subtract {
+            true => al.wrapping_sub(0x60),
+            false => al.wrapping_add(0x60),
+        };
+        mem.set_flag(Flag::CF, true);
+    } else {
+        mem.set_flag(Flag::CF, false);
+    }
+
+    set_szp(mem, u16::from(al), false);
+    al
+}
+
+/// AAA/AAS ASCII adjust of AX after an addition or subtraction
+pub fn ascii_adjust(mem: &mut Memory, ax: u16, subtract: bool) -> u16 {
+    let (mut ah, mut al) = ((ax >> 8) as u8, ax as u8);
+
+    let adjust = al & 0x0F > 9 || mem.get_flag(Flag::AF);
+    if adjust {
+        match subtract {
+            true => {
+                al = al.wrapping_sub(6);
+                ah = ah.wrapping_sub(1);
+            },
+            false => {
+                al = al.wrapping_add(6);
+                ah = ah.wrapping_add(1);
+            },
+        }
+    }
+
+    mem.set_flag(Flag::AF, adjust);
+    mem.set_flag(Flag::CF, adjust);
+    u16::from(ah) << 8 | u16::from(al & 0x0F)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_sets_carry_auxiliary_and_overflow() {
+        let mut mem = Memory::new();
+        assert_eq!(add(&mut mem, 0x7FFF, 1, true, false), 0x8000);
+        assert_eq!(mem.flags_string(), "PASO");
+
+        assert_eq!(add(&mut mem, 0xFFFF, 1, true, false), 0);
+        assert_eq!(mem.flags_string(), "CPAZ");
+
+        assert_eq!(add(&mut mem, 0x80, 0x80, false, false), 0);
+        assert_eq!(mem.flags_string(), "CPZO");
+
+        assert_eq!(add(&mut mem, 0x0F, 0x01, false, false), 0x10);
+        assert_eq!(mem.flags_string(), "A");
+
+        // ADC carries into the result
+        assert_eq!(add(&mut mem, 0xFE, 0x01, false, true), 0);
+        assert_eq!(mem.flags_string(), "CPAZ");
+    }
+
+    #[test]
+    fn sub_sets_borrow_auxiliary_and_overflow() {
+        let mut mem = Memory::new();
+        assert_eq!(sub(&mut mem, 0x8000, 1, true, false), 0x7FFF);
+        assert_eq!(mem.flags_string(), "PAO");
+
+        assert_eq!(sub(&mut mem, 0, 1, true, false), 0xFFFF);
+        assert_eq!(mem.flags_string(), "CPAS");
+
+        assert_eq!(sub(&mut mem, 0x10, 0x10, false, false), 0);
+        assert_eq!(mem.flags_string(), "PZ");
+
+        // SBB borrows one more
+        assert_eq!(sub(&mut mem, 0x10, 0x0F, false, true), 0);
+        assert_eq!(mem.flags_string(), "PAZ");
+        assert_eq!(sub(&mut mem, 0, 0, false, true), 0xFF);
+        assert_eq!(mem.flags_string(), "CPAS");
+    }
+
+    #[test]
+    fn shift_by_one_sets_overflow_from_the_sign_change() {
+        let mut mem = Memory::new();
+        assert_eq!(shift(&mut mem, Shift::Shl, 0x40, 1, false), 0x80);
+        assert!(mem.get_flag(Flag::OF) && !mem.get_flag(Flag::CF));
+
+        assert_eq!(shift(&mut mem, Shift::Shl, 0xC0, 1, false), 0x80);
+        assert!(!mem.get_flag(Flag::OF) && mem.get_flag(Flag::CF));
+
+        // SHR's OF is the original sign bit; SAR never overflows
+        assert_eq!(shift(&mut mem, Shift::Shr, 0x8000, 1, true), 0x4000);
+        assert!(mem.get_flag(Flag::OF) && !mem.get_flag(Flag::CF));
+        assert_eq!(shift(&mut mem, Shift::Sar, 0x8001, 1, true), 0xC000);
+        assert!(!mem.get_flag(Flag::OF) && mem.get_flag(Flag::CF));
+
+        assert_eq!(shift(&mut mem, Shift::Ror, 0x01, 1, false), 0x80);
+        assert!(mem.get_flag(Flag::OF) && mem.get_flag(Flag::CF));
+    }
+
+    #[test]
+    fn rotate_through_carry() {
+        let mut mem = Memory::new();
+        mem.set_flag(Flag::CF, true);
+        assert_eq!(shift(&mut mem, Shift::Rcl, 0x80, 1, false), 0x01);
+        assert!(mem.get_flag(Flag::CF));
+        assert_eq!(shift(&mut mem, Shift::Rcr, 0x00, 1, false), 0x80);
+        assert!(!mem.get_flag(Flag::CF));
+    }
+
+    #[test]
+    fn decimal_adjust_after_addition_and_subtraction() {
+        let mut mem = Memory::new();
+        // 0x38 + 0x45 = 0x7D, which is 83 in BCD
+        add(&mut mem, 0x38, 0x45, false, false);
+        assert_eq!(decimal_adjust(&mut mem, 0x7D, false), 0x83);
+        assert!(mem.get_flag(Flag::AF) && !mem.get_flag(Flag::CF));
+
+        // 0x99 + 0x01 = 0x9A, which is 100 in BCD
+        add(&mut mem, 0x99, 0x01, false, false);
+        assert_eq!(decimal_adjust(&mut mem, 0x9A, false), 0x00);
+        assert!(mem.get_flag(Flag::CF) && mem.get_flag(Flag::ZF));
+
+        // 0x10 - 0x01 = 0x0F, which is 09 in BCD
+        sub(&mut mem, 0x10, 0x01, false, false);
+        assert_eq!(decimal_adjust(&mut mem, 0x0F, true), 0x09);
+        assert!(mem.get_flag(Flag::AF) && !mem.get_flag(Flag::CF));
+    }
+
+    #[test]
+    fn ascii_adjust_after_addition_and_subtraction() {
+        let mut mem = Memory::new();
+        // '9' + '3' leaves AL=0x6C; unpacked BCD 12
+        add(&mut mem, 0x39, 0x33, false, false);
+        assert_eq!(ascii_adjust(&mut mem, 0x006C, false), 0x0102);
+        assert!(mem.get_flag(Flag::AF) && mem.get_flag(Flag::CF));
+
+        add(&mut mem, 0x32, 0x33, false, false);
+        assert_eq!(ascii_adjust(&mut mem, 0x0065, false), 0x0005);
+        assert!(!mem.get_flag(Flag::AF) && !mem.get_flag(Flag::CF));
+
+        // '3' - '5' borrows from AH
+        sub(&mut mem, 0x33, 0x35, false, false);
+        assert_eq!(ascii_adjust(&mut mem, 0x01FE, true), 0x0008);
+        assert!(mem.get_flag(Flag::CF));
+    }
+
+    #[test]
+    fn divide_errors_on_zero_and_overflow() {
+        assert_eq!(div(0, 100, 7, false, false), Some((14, 2)));
+        assert_eq!(div(0, 100, 0, false, false), None);
+        assert_eq!(div(0, 0x1000, 2, false, false), None);
+        assert_eq!(div(0xFFFF, 0xFF9C, 7, true, true), Some((0xFFF2, 0xFFFE)));
+    }
+}
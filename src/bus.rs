@@ -0,0 +1,158 @@
+use std::fmt::{self, Formatter};
+use std::ops::AddAssign;
+use super::clocks::Model;
+use super::mem::Access;
+
+/// Bus transfers made by one instruction, or a running total of them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Transfers {
+    pub fetch: u32, // Instruction bytes, when counted
+    pub reads: u32,
+    pub writes: u32,
+    pub odd_words: u32, // Word accesses at odd addresses
+}
+
+impl Transfers {
+    pub fn total(&self) -> u32 {
+        self.fetch + self.reads + self.writes
+    }
+}
+
+impl AddAssign for Transfers {
+    fn add_assign(&mut self, other: Self) {
+        self.fetch += other.fetch;
+        self.reads += other.reads;
+        self.writes += other.writes;
+        self.odd_words += other.odd_words;
+    }
+}
+
+impl fmt::Display for Transfers {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.total())?;
+
+        let mut parts = Vec::new();
+        if self.fetch != 0 {
+            parts.push(format!("{}f", self.fetch));
+        }
+        if self.reads != 0 {
+            parts.push(format!("{}r", self.reads));
+        }
+        if self.writes != 0 {
+            parts.push(format!("{}w", self.writes));
+        }
+
+        match (parts.len(), self.odd_words) {
+            (0 | 1, 0) => {},
+            (_, 0) => write!(f, " ({})", parts.join(" + "))?,
+            (_, odd_words) => write!(f, " ({}, {} odd)", parts.join(" + "), odd_words)?,
+        }
+        Ok(())
+    }
+}
+
+/// Bus transfers needed for one data access. The 8086 moves an aligned word at once
+/// but needs two transfers for a word at an odd address; the 8088 moves a byte at a time.
+pub fn access_transfers(model: Model, access: &Access) -> u32 {
+    match (model, access.wide) {
+        (_, false) => 1,
+        (Model::I8086, true) => 1 + u32::from(access.odd_word()),
+        (Model::I8088, true) => 2,
+    }
+}
+
+/// Bus transfers to fetch `len` instruction bytes starting at physical address `addr`
+pub fn fetch_transfers(model: Model, addr: u32, len: usize) -> u32 {
+    let len = len as u32;
+    match (model, len) {
+        (_, 0) => 0,
+        // Every aligned word the bytes touch
+        (Model::I8086, _) => (addr + len - 1) / 2 - addr / 2 + 1,
+        (Model::I8088, _) => len,
+    }
+}
+
+/// Tallies the transfers made by a list of data accesses, leaving out emulated services' writes
+pub fn transfers(model: Model, accesses: &[Access]) -> Transfers {
+    let mut transfers = Transfers::default();
+    for access in accesses.iter().filter(|access| !access.emulated) {
+        let count = access_transfers(model, access);
+        match access.write {
+            true => transfers.writes += count,
+            false => transfers.reads += count,
+        }
+        if access.odd_word() {
+            transfers.odd_words += 1;
+        }
+    }
+    transfers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BYTE: Access = Access { addr: 0x1001, wide: false, write: false, emulated: false };
+    const EVEN_WORD: Access = Access { addr: 0x1000, wide: true, write: false, emulated: false };
+    const ODD_WORD: Access = Access { addr: 0x1001, wide: true, write: true, emulated: false };
+
+    #[test]
+    fn access_transfers_by_model() {
+        assert_eq!(access_transfers(Model::I8086, &BYTE), 1);
+        assert_eq!(access_transfers(Model::I8086, &EVEN_WORD), 1);
+        assert_eq!(access_transfers(Model::I8086, &ODD_WORD), 2);
+        assert_eq!(access_transfers(Model::I8088, &BYTE), 1);
+        assert_eq!(access_transfers(Model::I8088, &EVEN_WORD), 2);
+        assert_eq!(access_transfers(Model::I8088, &ODD_WORD), 2);
+    }
+
+    #[test]
+    fn fetch_transfers_by_alignment() {
+        assert_eq!(fetch_transfers(Model::I8086, 0x100, 0), 0);
+        assert_eq!(fetch_transfers(Model::I8086, 0x100, 1), 1);
+        assert_eq!(fetch_transfers(Model::I8086, 0x100, 2), 1);
+        assert_eq!(fetch_transfers(Model::I8086, 0x101, 2), 2);
+        assert_eq!(fetch_transfers(Model::I8086, 0x101, 3), 2);
+        assert_eq!(fetch_transfers(Model::I8086, 0x100, 4), 2);
+        assert_eq!(fetch_transfers(Model::I8088, 0x101, 3), 3);
+    }
+
+    #[test]
+    fn transfers_tally_reads_writes_and_odd_words() {
+        let accesses = [BYTE, EVEN_WORD, ODD_WORD];
+        assert_eq!(transfers(Model::I8086, &accesses), Transfers { fetch: 0, reads: 2, writes: 2, odd_words: 1 });
+        assert_eq!(transfers(Model::I8088, &accesses), Transfers { fetch: 0, reads: 3, writes: 2, odd_words: 1 });
+    }
+
+    #[test]
+    fn cpu_counts_transfers_per_instruction() {
+        use crate::cpu::Cpu;
+
+        // mov bx, 1001; mov [bx], ax; push ax
+        let program = [0xBB, 0xE9, 0x03, 0x89, 0x07, 0x50];
+        let mut cpu = Cpu::new();
+        cpu.load(&program);
+        cpu.count_fetch = true;
+        let steps: Vec<Transfers> = (0..3).map(|_| cpu.step().unwrap().transfers).collect();
+        assert_eq!(steps[0], Transfers { fetch: 2, reads: 0, writes: 0, odd_words: 0 });
+        assert_eq!(steps[1], Transfers { fetch: 2, reads: 0, writes: 2, odd_words: 1 });
+        assert_eq!(steps[2], Transfers { fetch: 1, reads: 0, writes: 1, odd_words: 0 });
+        assert_eq!(cpu.transfers.total(), 8);
+    }
+
+    #[test]
+    fn emulated_service_counts_only_the_interrupt_sequence() {
+        use crate::cpu::Cpu;
+
+        // mov ax, 3; int 10h, which sets the mode and so clears the screen; hlt
+        let program = [0xB8, 0x03, 0x00, 0xCD, 0x10, 0xF4];
+        let mut cpu = Cpu::new();
+        cpu.load(&program);
+        cpu.step().unwrap();
+        let step = cpu.step().unwrap();
+
+        // FLAGS, CS and IP pushed and the vector's two words read; the 4000 framebuffer bytes aren't bus traffic
+        assert_eq!(step.transfers, Transfers { fetch: 0, reads: 2, writes: 3, odd_words: 0 });
+        assert_eq!(cpu.mem.accesses.iter().filter(|access| access.emulated && access.write).count(), 4000);
+    }
+}
@@ -0,0 +1,349 @@
+use std::fmt::{self, Formatter};
+use super::instruction::*;
+use super::mem::*;
+use super::operand::*;
+
+/// CPU whose timings are estimated. The 8088 has an 8-bit bus, so 16-bit transfers cost more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Model {
+    #[default]
+    I8086,
+    I8088,
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::I8086 => write!(f, "8086"),
+            Self::I8088 => write!(f, "8088"),
+        }
+    }
+}
+
+/// What happened when an instruction executed, for timings that depend on it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Outcome {
+    pub jumped: bool, // Conditional jump or loop was taken
+    pub shift_count: u8, // CL for shifts and rotates by CL
+    pub repeats: u16, // Iterations of a REP string instruction
+    pub odd_words: u32, // Word accesses at odd addresses
+}
+
+/// Clock estimate for one instruction, split the way the 8086 manual's timing tables are
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Clocks {
+    pub base: u32,
+    pub ea: u32, // Effective address calculation
+    pub penalty: u32, // Extra clocks for 16-bit transfers on an 8-bit bus, or at odd addresses on the 8086
+}
+
+impl Clocks {
+    pub fn total(&self) -> u32 {
+        self.base + self.ea + self.penalty
+    }
+}
+
+impl fmt::Display for Clocks {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.total())?;
+        if self.ea != 0 || self.penalty != 0 {
+            write!(f, " ({}", self.base)?;
+            if self.ea != 0 {
+                write!(f, " + {}ea", self.ea)?;
+            }
+            if self.penalty != 0 {
+                write!(f, " + {}p", self.penalty)?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// Effective address calculation clocks, including a segment override
+pub fn ea_clocks(mem_op: &MemOperand) -> u32 {
+    let has_disp = mem_op.disp.is_some();
+    let clocks = match (mem_op.ea, has_disp) {
+        (EffectiveAddress::Direct, _) => 6,
+        (EffectiveAddress::BX | EffectiveAddress::BP | EffectiveAddress::SI | EffectiveAddress::DI, false) => 5,
+        (EffectiveAddress::BX | EffectiveAddress::BP | EffectiveAddress::SI | EffectiveAddress::DI, true) => 9,
+        (EffectiveAddress::BP_DI | EffectiveAddress::BX_SI, false) => 7,
+        (EffectiveAddress::BP_SI | EffectiveAddress::BX_DI, false) => 8,
+        (EffectiveAddress::BP_DI | EffectiveAddress::BX_SI, true) => 11,
+        (EffectiveAddress::BP_SI | EffectiveAddress::BX_DI, true) => 12,
+        (EffectiveAddress::UNIMPL, _) => 0,
+    };
+
+    match mem_op.segment {
+        Some(_) => clocks + 2,
+        None => clocks,
+    }
+}
+
+/// Base clocks and memory transfers of an instruction, from the 8086 manual's timing tables.
+/// Data-dependent timings such as MUL and DIV use the low end of their range.
+fn base_clocks(inst: &Instruction, outcome: &Outcome) -> (u32, u32) {
+    let to_mem = inst.dest.is_some_and(|op| op.is_mem());
+    let from_mem = inst.source.is_some_and(|op| op.is_mem());
+    let mem = to_mem || from_mem;
+    let reps = u32::from(outcome.repeats);
+    let count = u32::from(outcome.shift_count);
+
+    match inst.opcode {
+        Opcode::MovRmToReg | Opcode::MovRmToSeg | Opcode::MovSegToRm => match (to_mem, from_mem) {
+            (false, false) => (2, 0),
+            (false, true) => (8, 1),
+            (true, _) => (9, 1),
+        },
+        Opcode::MovMemToAcc | Opcode::MovAccToMem => (10, 1),
+        Opcode::MovImmToReg => (4, 0),
+        Opcode::MovImmToRm => if mem { (10, 1) } else { (4, 0) },
+
+        Opcode::AddRmAndReg | Opcode::AdcRmAndReg | Opcode::SubRmAndReg | Opcode::SbbRmAndReg |
+        Opcode::AndRmAndReg | Opcode::OrRmAndReg | Opcode::XorRmAndReg => match (to_mem, from_mem) {
+            (false, false) => (3, 0),
+            (false, true) => (9, 1),
+            (true, _) => (16, 2),
+        },
+        Opcode::CmpRmAndReg | Opcode::TestRmAndReg => if mem { (9, 1) } else { (3, 0) },
+        Opcode::ImmToRm => match (inst.op_type, mem) {
+            (_, false) => (4, 0),
+            (Some(OpType::CMP), true) => (10, 1),
+            (_, true) => (17, 2),
+        },
+        Opcode::AddImmToAcc | Opcode::AdcImmToAcc | Opcode::SubImmFromAcc | Opcode::SbbImmFromAcc |
+        Opcode::CmpImmToAcc | Opcode::AndImmToAcc | Opcode::OrImmToAcc | Opcode::XorImmToAcc |
+        Opcode::TestImmToAcc => (4, 0),
+        Opcode::TestImmToRm => if mem { (11, 1) } else { (5, 0) },
+
+        Opcode::IncReg | Opcode::DecReg => (2, 0),
+        Opcode::IncRm | Opcode::DecRm => match (mem, inst.w) {
+            (true, _) => (15, 2),
+            (false, true) => (2, 0),
+            (false, false) => (3, 0),
+        },
+        Opcode::Neg | Opcode::Not => if mem { (16, 2) } else { (3, 0) },
+        Opcode::Mul => match (mem, inst.w) {
+            (false, false) => (70, 0),
+            (false, true) => (118, 0),
+            (true, false) => (76, 1),
+            (true, true) => (124, 1),
+        },
+        Opcode::Imul => match (mem, inst.w) {
+            (false, false) => (80, 0),
+            (false, true) => (128, 0),
+            (true, false) => (86, 1),
+            (true, true) => (134, 1),
+        },
+        Opcode::Div => match (mem, inst.w) {
+            (false, false) => (80, 0),
+            (false, true) => (144, 0),
+            (true, false) => (86, 1),
+            (true, true) => (150, 1),
+        },
+        Opcode::Idiv => match (mem, inst.w) {
+            (false, false) => (101, 0),
+            (false, true) => (165, 0),
+            (true, false) => (107, 1),
+            (true, true) => (171, 1),
+        },
+        Opcode::Aaa | Opcode::Aas | Opcode::Daa | Opcode::Das => (4, 0),
+        Opcode::Aam => (83, 0),
+        Opcode::Aad => (60, 0),
+        Opcode::Cbw => (2, 0),
+        Opcode::Cwd => (5, 0),
+
+        Opcode::Shl | Opcode::Shr | Opcode::Sar | Opcode::Rol | Opcode::Ror | Opcode::Rcl | Opcode::Rcr => {
+            match (inst.d, mem) {
+                (false, false) => (2, 0),
+                (false, true) => (15, 2),
+                (true, false) => (8 + 4 * count, 0),
+                (true, true) => (20 + 4 * count, 2),
+            }
+        },
+
+        // REP forms cost 9 plus a per-iteration time
+        Opcode::Movs => if inst.rep.is_some() { (9 + 17 * reps, 2 * reps) } else { (18, 2) },
+        Opcode::Cmps => if inst.rep.is_some() { (9 + 22 * reps, 2 * reps) } else { (22, 2) },
+        Opcode::Scas => if inst.rep.is_some() { (9 + 15 * reps, reps) } else { (15, 1) },
+        Opcode::Lods => if inst.rep.is_some() { (9 + 13 * reps, reps) } else { (12, 1) },
+        Opcode::Stos => if inst.rep.is_some() { (9 + 10 * reps, reps) } else { (11, 1) },
+
+        Opcode::JmpEqual | Opcode::JmpLess | Opcode::JmpLessOrEqual | Opcode::JmpBelow | Opcode::JmpBelowOrEqual |
+        Opcode::JmpParity | Opcode::JmpOverflow | Opcode::JmpSign | Opcode::JmpNotEqual | Opcode::JmpNotLess |
+        Opcode::JmpNotLessOrEqual | Opcode::JmpNotBelow | Opcode::JmpNotBelowOrEqual | Opcode::JmpNotParity |
+        Opcode::JmpNotOverflow | Opcode::JmpOnNotSign => if outcome.jumped { (16, 0) } else { (4, 0) },
+        Opcode::JmpCXZero | Opcode::LoopZero => if outcome.jumped { (18, 0) } else { (6, 0) },
+        Opcode::Loop => if outcome.jumped { (17, 0) } else { (5, 0) },
+        Opcode::LoopNotZero => if outcome.jumped { (19, 0) } else { (5, 0) },
+        Opcode::JmpShort | Opcode::JmpDirect | Opcode::JmpDirectFar => (15, 0),
+        Opcode::JmpIndirect => if mem { (18, 1) } else { (11, 0) },
+        Opcode::JmpIndirectFar => (24, 2),
+        Opcode::CallDirect => (19, 1),
+        Opcode::CallDirectFar => (28, 2),
+        Opcode::CallIndirect => if mem { (21, 2) } else { (16, 1) },
+        Opcode::CallIndirectFar => (37, 4),
+        Opcode::Ret => (8, 1),
+        Opcode::RetImm => (12, 1),
+        Opcode::RetFar => (18, 2),
+        Opcode::RetFarImm => (17, 2),
+        Opcode::Int => (51, 5),
+        Opcode::Int3 => (52, 5),
+        Opcode::Into => if outcome.jumped { (53, 5) } else { (4, 0) },
+        Opcode::Iret => (24, 3),
+
+        Opcode::PushReg => (11, 1),
+        Opcode::PushSeg => (10, 1),
+        Opcode::PushRm => if mem { (16, 2) } else { (11, 1) },
+        Opcode::PopReg | Opcode::PopSeg => (8, 1),
+        Opcode::PopRm => if mem { (17, 2) } else { (8, 1) },
+        Opcode::Pushf => (10, 1),
+        Opcode::Popf => (8, 1),
+        Opcode::XchgRegWithAcc => (3, 0),
+        Opcode::XchgRmWithReg => if mem { (17, 2) } else { (4, 0) },
+        Opcode::InFixed | Opcode::OutFixed => (10, 1),
+        Opcode::InVariable | Opcode::OutVariable => (8, 1),
+        Opcode::Xlat => (11, 1),
+        Opcode::Lea => (2, 0),
+        Opcode::Lds | Opcode::Les => (16, 2),
+        Opcode::Lahf | Opcode::Sahf => (4, 0),
+
+        Opcode::Clc | Opcode::Cmc | Opcode::Stc | Opcode::Cld | Opcode::Std | Opcode::Cli | Opcode::Sti |
+        Opcode::Hlt => (2, 0),
+        Opcode::Wait => (3, 0),
+        Opcode::Esc => if mem { (8, 1) } else { (2, 0) },
+        Opcode::Lock | Opcode::Rep | Opcode::Segment | Opcode::Unimpl => (0, 0),
+    }
+}
+
+/// Whether an instruction's memory transfers are words regardless of W
+fn word_transfers(opcode: Opcode) -> bool {
+    matches!(opcode,
+        Opcode::PushRm | Opcode::PushReg | Opcode::PushSeg | Opcode::PopRm | Opcode::PopReg | Opcode::PopSeg |
+        Opcode::Pushf | Opcode::Popf | Opcode::CallDirect | Opcode::CallIndirect | Opcode::CallDirectFar |
+        Opcode::CallIndirectFar | Opcode::JmpIndirect | Opcode::JmpIndirectFar | Opcode::Ret | Opcode::RetImm |
+        Opcode::RetFar | Opcode::RetFarImm | Opcode::Int | Opcode::Int3 | Opcode::Into | Opcode::Iret |
+        Opcode::Lds | Opcode::Les)
+}
+
+/// Estimates the clocks an executed instruction took on `model`
+pub fn estimate(inst: &Instruction, model: Model, outcome: &Outcome) -> Clocks {
+    let (base, transfers) = base_clocks(inst, outcome);
+
+    let ea = [inst.dest, inst.source].iter().flatten()
+        .find_map(|op| match op {
+            Operand::Mem(mem_op) => Some(ea_clocks(mem_op)),
+            _ => None,
+        })
+        .unwrap_or(0);
+
+    // The accumulator forms' clocks already include their direct address
+    let ea = match inst.opcode {
+        Opcode::MovMemToAcc | Opcode::MovAccToMem => 0,
+        _ => ea,
+    };
+
+    let penalty = match (model, inst.w || word_transfers(inst.opcode)) {
+        (Model::I8086, _) => 4 * outcome.odd_words,
+        (Model::I8088, true) => 4 * transfers,
+        (Model::I8088, false) => 0,
+    };
+
+    Clocks { base, ea, penalty }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::decode::decode;
+
+    /// The movs and adds of the course's timing listing, with their 8086 and 8088 clocks from the manual
+    const TIMING_LISTING: [(&[u8], u32, u32); 18] = [
+        (&[0xBB, 0xE8, 0x03], 4, 4),         // mov bx, 1000
+        (&[0xBD, 0xD0, 0x07], 4, 4),         // mov bp, 2000
+        (&[0xBE, 0xB8, 0x0B], 4, 4),         // mov si, 3000
+        (&[0xBF, 0xA0, 0x0F], 4, 4),         // mov di, 4000
+        (&[0x89, 0xD9], 2, 2),               // mov cx, bx
+        (&[0xBA, 0x0C, 0x00], 4, 4),         // mov dx, 12
+        (&[0x8B, 0x16, 0xE8, 0x03], 14, 18), // mov dx, [1000]: 8 + 6ea
+        (&[0x8B, 0x0F], 13, 17),             // mov cx, [bx]: 8 + 5ea
+        (&[0x8B, 0x4E, 0x00], 17, 21),       // mov cx, [bp], which is [bp + 0]: 8 + 9ea
+        (&[0x89, 0x0C], 14, 18),             // mov [si], cx: 9 + 5ea
+        (&[0x89, 0x0D], 14, 18),             // mov [di], cx
+        (&[0x8B, 0x8F, 0xE8, 0x03], 17, 21), // mov cx, [bx + 1000]: 8 + 9ea
+        (&[0x8B, 0x8E, 0xE8, 0x03], 17, 21), // mov cx, [bp + 1000]
+        (&[0x89, 0x8C, 0xE8, 0x03], 18, 22), // mov [si + 1000], cx: 9 + 9ea
+        (&[0x89, 0x8D, 0xE8, 0x03], 18, 22), // mov [di + 1000], cx
+        (&[0x01, 0xD1], 3, 3),               // add cx, dx
+        (&[0x01, 0x8D, 0xE8, 0x03], 25, 33), // add [di + 1000], cx: 16 + 9ea, two word transfers
+        (&[0x83, 0xC2, 0x32], 4, 4),         // add dx, 50
+    ];
+
+    fn run_clocks(program: &[u8], model: Model) -> Vec<u32> {
+        let mut cpu = Cpu::new();
+        cpu.load(program);
+        cpu.model = model;
+        let mut clocks = Vec::new();
+        while cpu.running() {
+            clocks.push(cpu.step().unwrap().clocks.total());
+        }
+        clocks
+    }
+
+    fn clocks_of(bytes: &[u8], model: Model, outcome: &Outcome) -> Clocks {
+        estimate(&decode(bytes).unwrap(), model, outcome)
+    }
+
+    #[test]
+    fn timing_listing_8086() {
+        let program: Vec<u8> = TIMING_LISTING.iter().flat_map(|(bytes, _, _)| bytes.iter().copied()).collect();
+        let expected: Vec<u32> = TIMING_LISTING.iter().map(|(_, i8086, _)| *i8086).collect();
+        assert_eq!(run_clocks(&program, Model::I8086), expected);
+        assert_eq!(expected.iter().sum::<u32>(), 196);
+    }
+
+    #[test]
+    fn timing_listing_8088() {
+        let program: Vec<u8> = TIMING_LISTING.iter().flat_map(|(bytes, _, _)| bytes.iter().copied()).collect();
+        let expected: Vec<u32> = TIMING_LISTING.iter().map(|(_, _, i8088)| *i8088).collect();
+        assert_eq!(run_clocks(&program, Model::I8088), expected);
+    }
+
+    #[test]
+    fn odd_word_penalty_8086() {
+        // mov bx, 1001; mov cx, [bx]; mov cl, [bx]; mov [bx + 1], cx
+        let program = [0xBB, 0xE9, 0x03, 0x8B, 0x0F, 0x8A, 0x0F, 0x89, 0x4F, 0x01];
+        assert_eq!(run_clocks(&program, Model::I8086), [4, 13 + 4, 13, 18]);
+    }
+
+    #[test]
+    fn ea_clocks_by_form() {
+        let outcome = Outcome::default();
+        let ea = |bytes: &[u8]| clocks_of(bytes, Model::I8086, &outcome).ea;
+        assert_eq!(ea(&[0x8B, 0x00]), 7);             // [bx + si]
+        assert_eq!(ea(&[0x8B, 0x02]), 8);             // [bp + si]
+        assert_eq!(ea(&[0x8B, 0x41, 0x05]), 12);      // [bx + di + 5]
+        assert_eq!(ea(&[0x8B, 0x43, 0x05]), 11);      // [bp + di + 5]
+        assert_eq!(ea(&[0x26, 0x8B, 0x07]), 5 + 2);   // es:[bx]
+        assert_eq!(ea(&[0xA1, 0xE8, 0x03]), 0);       // mov ax, [1000] includes its address
+    }
+
+    #[test]
+    fn outcome_dependent_clocks() {
+        let taken = Outcome { jumped: true, ..Outcome::default() };
+        let not_taken = Outcome::default();
+        assert_eq!(clocks_of(&[0x74, 0x02], Model::I8086, &taken).total(), 16);     // je
+        assert_eq!(clocks_of(&[0x74, 0x02], Model::I8086, &not_taken).total(), 4);
+        assert_eq!(clocks_of(&[0xE2, 0xFE], Model::I8086, &taken).total(), 17);     // loop
+        assert_eq!(clocks_of(&[0xE2, 0xFE], Model::I8086, &not_taken).total(), 5);
+
+        let shift = Outcome { shift_count: 3, ..Outcome::default() };
+        assert_eq!(clocks_of(&[0xD3, 0xE0], Model::I8086, &shift).total(), 8 + 4 * 3); // shl ax, cl
+
+        let repeats = Outcome { repeats: 10, ..Outcome::default() };
+        assert_eq!(clocks_of(&[0xF3, 0xA4], Model::I8086, &repeats).total(), 9 + 17 * 10); // rep movsb
+        // Each iteration moves a word in and out over the 8088's byte bus
+        assert_eq!(clocks_of(&[0xF3, 0xA5], Model::I8088, &repeats).total(), 9 + 17 * 10 + 4 * 20); // rep movsw
+    }
+}
@@ -0,0 +1,469 @@
+use std::collections::VecDeque;
+use super::bus::{self, Transfers};
+use super::clocks::{self, Clocks, Model, Outcome};
+use super::decode::{decode, DecodeError};
+use super::instruction::{Instruction, Opcode};
+use super::dos::Dos;
+use super::exe::{Exe, ExeError};
+use super::mem::*;
+use super::operand::{Immediate, Operand};
+use super::video::{self, Video};
+
+/// Bytes fetched at CS:IP for decoding, enough for the longest instruction plus prefixes
+pub const MAX_INST_LEN: u16 = 16;
+
+/// Segment the PSP of a DOS program is built at; the program follows it
+pub const PSP_SEGMENT: u16 = 0x1000;
+
+/// Bytes in the PSP, which is also where a .COM image starts within its segment
+pub const PSP_LEN: u16 = 0x100;
+
+/// Segment just past the conventional memory available to DOS programs
+const TOP_OF_MEMORY: u16 = 0xA000;
+
+/// Longest command tail the PSP holds, leaving room for the terminating CR
+const MAX_TAIL_LEN: usize = 126;
+
+/// Instructions kept in the undo history; older ones can't be stepped back over
+const HISTORY_LEN: usize = 100_000;
+
+/// What one instruction changed, enough to put the CPU back the way it was before it ran.
+/// Memory is journaled byte by byte rather than copied. Host files DOS wrote to stay written.
+#[derive(Debug, Clone)]
+struct Record {
+    regs: [u16; REGISTER_COUNT],
+    journal: Vec<Undo>,
+    clocks: u64,
+    transfers: Transfers,
+    halted: bool,
+    exit_code: Option<u8>,
+    video: Video,
+}
+
+/// An executed instruction and what it cost
+pub struct Step {
+    pub inst: Instruction,
+    pub clocks: Clocks,
+    pub transfers: Transfers,
+}
+
+/// An 8086: registers, flags and memory, plus the program loaded into it
+pub struct Cpu {
+    pub mem: Memory,
+    pub code_end: usize, // Offset in CS just past the loaded program
+    pub halted: bool, // Stopped by HLT or a DOS terminate
+    pub dos: Dos, // INT 20h/21h services
+    pub video: Video, // INT 10h services
+    pub model: Model, // Timings used for clock estimates
+    pub clocks: u64, // Estimated clocks of every instruction executed so far
+    pub count_fetch: bool, // Include instruction fetch in bus transfers
+    pub transfers: Transfers, // Bus transfers of every instruction executed so far
+    pub executed: u64, // Instructions executed so far
+    history: Option<VecDeque<Record>>, // Newest last, while recording
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        let mut mem = Memory::new();
+        video::clear(&mut mem);
+        // Powering on isn't an access anything should see
+        mem.accesses.clear();
+
+        Cpu {
+            mem,
+            code_end: 0,
+            halted: false,
+            dos: Dos::new("."),
+            video: Video::new(),
+            model: Model::default(),
+            clocks: 0,
+            count_fetch: false,
+            transfers: Transfers::default(),
+            executed: 0,
+            history: None,
+        }
+    }
+
+    /// Loads a program at CS:0000, where execution starts
+    pub fn load(&mut self, program: &[u8]) {
+        let code_start = physical_address(self.mem.read_seg(SegReg::CS), 0);
+        self.mem.load(code_start, program);
+        self.code_end = program.len();
+    }
+
+    /// Builds the PSP DOS puts in front of a program, holding its command tail
+    fn load_psp(&mut self, tail: &str) {
+        // INT 20h at offset 0, so a near RET to 0000h terminates a .COM program
+        let mut psp = vec![0; usize::from(PSP_LEN)];
+        psp[0x00..0x02].copy_from_slice(&[0xCD, 0x20]);
+        psp[0x02..0x04].copy_from_slice(&TOP_OF_MEMORY.to_le_bytes());
+
+        let tail = &tail.as_bytes()[..tail.len().min(MAX_TAIL_LEN)];
+        psp[0x80] = tail.len() as u8;
+        psp[0x81..0x81 + tail.len()].copy_from_slice(tail);
+        psp[0x81 + tail.len()] = b'\r';
+
+        self.mem.load(physical_address(PSP_SEGMENT, 0), &psp);
+    }
+
+    /// Loads a DOS .COM program the way DOS would: a PSP holding the command tail at offset 0,
+    /// the image at 0100h, every segment register pointing at the PSP and SP at the top of the segment
+    pub fn load_com(&mut self, image: &[u8], tail: &str) {
+        self.load_psp(tail);
+        self.mem.load(physical_address(PSP_SEGMENT, PSP_LEN), image);
+        self.code_end = usize::from(PSP_LEN) + image.len();
+
+        for seg in [SegReg::CS, SegReg::DS, SegReg::ES, SegReg::SS] {
+            self.mem.write_seg(seg, PSP_SEGMENT);
+        }
+        // A zero on the stack sends a final RET to the INT 20h at the start of the PSP
+        self.mem.write_reg(Reg::SP, 0xFFFE);
+        self.mem.load(physical_address(PSP_SEGMENT, 0xFFFE), &[0, 0]);
+        self.mem.write_ip(PSP_LEN);
+    }
+
+    /// Loads a DOS MZ executable the way DOS would: the load module right after the PSP with its
+    /// relocations fixed up, DS and ES pointing at the PSP, and CS:IP and SS:SP from the header.
+    /// Fails, leaving memory untouched, if the module and its minimum allocation don't fit.
+    pub fn load_exe(&mut self, exe: &Exe, tail: &str) -> Result<(), ExeError> {
+        let load_segment = PSP_SEGMENT + PSP_LEN / 16;
+        let available = usize::from(TOP_OF_MEMORY - load_segment) * 16;
+        if exe.module.len() + usize::from(exe.min_alloc) * 16 > available {
+            return Err(ExeError::TooLarge);
+        }
+
+        self.load_psp(tail);
+        self.mem.load(physical_address(load_segment, 0), exe.module);
+
+        // Relocated words hold segments relative to the load module
+        for &(segment, offset) in &exe.relocations {
+            let addr = physical_address(load_segment.wrapping_add(segment), offset);
+            let val = u16::from_le_bytes([self.mem.read_byte(addr), self.mem.read_byte(addr + 1)]);
+            self.mem.load(addr, &val.wrapping_add(load_segment).to_le_bytes());
+        }
+
+        self.mem.write_seg(SegReg::DS, PSP_SEGMENT);
+        self.mem.write_seg(SegReg::ES, PSP_SEGMENT);
+        self.mem.write_seg(SegReg::CS, load_segment.wrapping_add(exe.cs));
+        self.mem.write_seg(SegReg::SS, load_segment.wrapping_add(exe.ss));
+        self.mem.write_reg(Reg::SP, exe.sp);
+        self.mem.write_ip(exe.ip);
+        // Code can span several segments, so run until the program halts or terminates
+        self.code_end = usize::from(u16::MAX) + 1;
+        Ok(())
+    }
+
+    /// Whether the program hasn't halted and IP is still inside it
+    pub fn running(&self) -> bool {
+        !self.halted && usize::from(self.mem.read_ip()) < self.code_end
+    }
+
+    /// Decodes the instruction at CS:IP without executing it
+    pub fn peek(&self) -> Result<Instruction, DecodeError> {
+        let ip = self.mem.read_ip();
+        // Don't read past the end of the program so a cut-off instruction reports as truncated
+        let count = self.code_end.saturating_sub(usize::from(ip)).min(usize::from(MAX_INST_LEN));
+        let inst_bytes = self.mem.fetch(self.mem.read_seg(SegReg::CS), ip, count as u16);
+        decode(&inst_bytes).map_err(|err| DecodeError { offset: usize::from(ip), ..err })
+    }
+
+    /// Fetches, decodes and executes the instruction at CS:IP, returning it with its clock estimate
+    pub fn step(&mut self) -> Result<Step, DecodeError> {
+        let inst = self.peek()?;
+        let pre_cx = self.mem.read_reg(Reg::CX);
+        let shift_count = self.mem.read_reg(Reg::CL) as u8;
+
+        // IP points past the instruction before it executes so jumps are relative to the next instruction
+        let next_ip = self.mem.read_ip().wrapping_add(inst.len as u16);
+        let fetch_addr = physical_address(self.mem.read_seg(SegReg::CS), self.mem.read_ip());
+        let record = self.history.is_some().then(|| {
+            self.mem.start_journal();
+            self.record()
+        });
+
+        self.mem.write_ip(next_ip);
+        self.mem.accesses.clear();
+        match inst.opcode {
+            Opcode::Hlt => self.halted = true,
+            // BIOS and DOS services are emulated rather than run through the vector table
+            Opcode::Int => match inst.dest {
+                Some(Operand::Imm(Immediate { value: vector @ (0x10 | 0x20 | 0x21), .. })) => {
+                    self.enter_service(vector as u8);
+                    match vector {
+                        0x10 => self.video.int10(&mut self.mem),
+                        0x20 => self.dos.exit_code = Some(0),
+                        _ => self.dos.int21(&mut self.mem),
+                    }
+                },
+                _ => inst.execute(&mut self.mem),
+            },
+            _ => inst.execute(&mut self.mem),
+        }
+        self.halted |= self.dos.exit_code.is_some();
+
+        let mut transfers = bus::transfers(self.model, &self.mem.accesses);
+        if self.count_fetch {
+            transfers.fetch = bus::fetch_transfers(self.model, fetch_addr, inst.len);
+        }
+        self.transfers += transfers;
+
+        let outcome = Outcome {
+            jumped: self.mem.read_ip() != next_ip || inst.opcode == Opcode::Into && self.mem.get_flag(Flag::OF),
+            shift_count,
+            repeats: pre_cx.wrapping_sub(self.mem.read_reg(Reg::CX)),
+            odd_words: transfers.odd_words,
+        };
+        let clocks = clocks::estimate(&inst, self.model, &outcome);
+        self.clocks += u64::from(clocks.total());
+        self.executed += 1;
+
+        if let (Some(history), Some(mut record)) = (&mut self.history, record) {
+            record.journal = self.mem.take_journal();
+            if history.len() == HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back(record);
+        }
+
+        Ok(Step { inst, clocks, transfers })
+    }
+
+    /// Bus traffic of an INT into an emulated service: FLAGS, CS and IP pushed and the vector read.
+    /// The service returns straight away, so the stack pointer is left as it was.
+    fn enter_service(&mut self, vector: u8) {
+        let sp = self.mem.read_reg(Reg::SP);
+        for val in [self.mem.read_flags(), self.mem.read_seg(SegReg::CS), self.mem.read_ip()] {
+            self.mem.push(val);
+        }
+        self.mem.write_reg(Reg::SP, sp);
+        self.mem.read_vector(vector);
+    }
+
+    /// Starts keeping the undo history that stepping backwards needs
+    pub fn record_history(&mut self) {
+        self.history.get_or_insert_with(VecDeque::new);
+    }
+
+    /// State before the next instruction, without its memory writes
+    fn record(&self) -> Record {
+        Record {
+            regs: self.mem.registers(),
+            journal: Vec::new(),
+            clocks: self.clocks,
+            transfers: self.transfers,
+            halted: self.halted,
+            exit_code: self.dos.exit_code,
+            video: self.video.clone(),
+        }
+    }
+
+    /// Undoes the last instruction executed. Returns false if it isn't in the history.
+    pub fn step_back(&mut self) -> bool {
+        let Some(record) = self.history.as_mut().and_then(VecDeque::pop_back) else {
+            return false;
+        };
+
+        self.mem.undo(&record.journal);
+        self.mem.set_registers(record.regs);
+        self.clocks = record.clocks;
+        self.transfers = record.transfers;
+        self.halted = record.halted;
+        self.dos.exit_code = record.exit_code;
+        self.video = record.video;
+        self.executed -= 1;
+        true
+    }
+
+    /// Steps back until `executed` instructions have run. Returns false, without moving,
+    /// if that's in the future or further back than the history goes.
+    pub fn rewind_to(&mut self, executed: u64) -> bool {
+        let recorded = self.history.as_ref().map_or(0, VecDeque::len) as u64;
+        if executed > self.executed || self.executed - executed > recorded {
+            return false;
+        }
+        while self.executed > executed {
+            self.step_back();
+        }
+        true
+    }
+
+    /// Instruction count just before the most recent instruction that changed a register or FLAGS
+    pub fn last_write(&self, loc: &str) -> Option<u64> {
+        let history = self.history.as_ref()?;
+        let mut after = Memory::register_in(&self.mem.registers(), loc)?;
+        for (idx, record) in history.iter().enumerate().rev() {
+            let before = Memory::register_in(&record.regs, loc)?;
+            if before != after {
+                return Some(self.executed - (history.len() - idx) as u64);
+            }
+            after = before;
+        }
+        None
+    }
+
+    /// Steps until the program halts or IP leaves it
+    pub fn run(&mut self) -> Result<(), DecodeError> {
+        while self.running() {
+            self.step()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Everything stepping back is meant to restore
+    #[derive(Debug, PartialEq)]
+    struct Snapshot {
+        regs: [u16; REGISTER_COUNT],
+        ram: Vec<u8>,
+        clocks: u64,
+        transfers: Transfers,
+        executed: u64,
+        halted: bool,
+        video: Video,
+    }
+
+    fn snapshot(cpu: &Cpu) -> Snapshot {
+        Snapshot {
+            regs: cpu.mem.registers(),
+            ram: cpu.mem.dump(0..MEM_SIZE).to_vec(),
+            clocks: cpu.clocks,
+            transfers: cpu.transfers,
+            executed: cpu.executed,
+            halted: cpu.halted,
+            video: cpu.video.clone(),
+        }
+    }
+
+    fn com(program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_com(program, "");
+        cpu.count_fetch = true;
+        cpu.record_history();
+        cpu
+    }
+
+    // MOV AX, 0E41h; INT 10h (teletype 'A'); MOV [200h], AX; ADD AX, 0FFFFh; HLT
+    const PROGRAM: [u8; 12] = [0xB8, 0x41, 0x0E, 0xCD, 0x10, 0xA3, 0x00, 0x02, 0x05, 0xFF, 0xFF, 0xF4];
+
+    #[test]
+    fn step_back_restores_snapshot() {
+        let mut cpu = com(&PROGRAM);
+        cpu.step().unwrap();
+        let after_first = snapshot(&cpu);
+
+        cpu.run().unwrap();
+        assert!(cpu.halted);
+        assert_eq!(cpu.executed, 5);
+        assert_ne!(snapshot(&cpu), after_first);
+
+        for _ in 0..4 {
+            assert!(cpu.step_back());
+        }
+        assert_eq!(snapshot(&cpu), after_first);
+    }
+
+    #[test]
+    fn rewind_to_restores_snapshot() {
+        let mut cpu = com(&PROGRAM);
+        let start = snapshot(&cpu);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let after_int = snapshot(&cpu);
+        assert_ne!(after_int.video, start.video); // The cursor moved
+
+        cpu.run().unwrap();
+        assert!(cpu.rewind_to(2));
+        assert_eq!(snapshot(&cpu), after_int);
+        assert!(cpu.rewind_to(0));
+        assert_eq!(snapshot(&cpu), start);
+        assert!(!cpu.step_back());
+
+        // Replaying from the start gets back to the same end state
+        cpu.run().unwrap();
+        let end = snapshot(&cpu);
+        assert!(cpu.rewind_to(3));
+        cpu.run().unwrap();
+        assert_eq!(snapshot(&cpu), end);
+    }
+
+    #[test]
+    fn rewind_to_rejects_unrecorded_counts() {
+        let mut cpu = com(&PROGRAM);
+        cpu.step().unwrap();
+        assert!(!cpu.rewind_to(2));
+        assert_eq!(cpu.executed, 1);
+    }
+
+    #[test]
+    fn history_drops_oldest_past_limit() {
+        // JMP $
+        let mut cpu = com(&[0xEB, 0xFE]);
+        for _ in 0..HISTORY_LEN + 10 {
+            cpu.step().unwrap();
+        }
+
+        let oldest = 10;
+        assert!(!cpu.rewind_to(oldest - 1));
+        assert!(cpu.rewind_to(oldest));
+        assert_eq!(cpu.executed, oldest);
+        assert!(!cpu.step_back());
+    }
+
+    #[test]
+    fn last_write_finds_instruction_that_changed_register() {
+        let mut cpu = com(&PROGRAM);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.last_write("AX"), Some(3)); // The ADD
+        assert_eq!(cpu.last_write("FLAGS"), Some(3));
+        assert_eq!(cpu.last_write("IP"), Some(4));
+        assert_eq!(cpu.last_write("CX"), None);
+        assert_eq!(cpu.last_write("XYZ"), None);
+
+        // Only what's still in the history counts
+        cpu.rewind_to(1);
+        assert_eq!(cpu.last_write("AX"), Some(0));
+    }
+
+    #[test]
+    fn com_programs_load_after_a_psp() {
+        let mut cpu = Cpu::new();
+        cpu.load_com(&[0xC3], "FOO.TXT /X");
+        let mem = &cpu.mem;
+        for seg in [SegReg::CS, SegReg::DS, SegReg::ES, SegReg::SS] {
+            assert_eq!(mem.read_seg(seg), PSP_SEGMENT, "{}", seg);
+        }
+        assert_eq!(mem.read_ip(), 0x100);
+        assert_eq!(mem.read_reg(Reg::SP), 0xFFFE);
+        assert_eq!(mem.fetch(PSP_SEGMENT, 0xFFFE, 2), [0, 0]);
+
+        // INT 20h, then the top of memory
+        assert_eq!(mem.fetch(PSP_SEGMENT, 0, 4), [0xCD, 0x20, 0x00, 0xA0]);
+        assert_eq!(mem.fetch(PSP_SEGMENT, 0x80, 13), b"\x0AFOO.TXT /X\r\0");
+        assert_eq!(mem.fetch(PSP_SEGMENT, 0x100, 1), [0xC3]);
+
+        // The final RET lands on the INT 20h
+        cpu.run().unwrap();
+        assert_eq!(cpu.mem.read_ip(), 0x02);
+        assert_eq!(cpu.dos.exit_code, Some(0));
+        assert!(!cpu.running());
+    }
+
+    #[test]
+    fn com_command_tails_are_truncated_to_fit_the_psp() {
+        let mut cpu = Cpu::new();
+        cpu.load_com(&[0xF4], &"A".repeat(200));
+        assert_eq!(cpu.mem.read_byte(physical_address(PSP_SEGMENT, 0x80)), MAX_TAIL_LEN as u8);
+        assert_eq!(cpu.mem.read_byte(physical_address(PSP_SEGMENT, 0x81 + MAX_TAIL_LEN as u16)), b'\r');
+    }
+}
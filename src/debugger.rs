@@ -0,0 +1,649 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use super::cpu::Cpu;
+use super::decode::decode;
+use super::instruction::{Instruction, Opcode};
+use super::mem::*;
+use super::operand::Operand;
+use super::watch::{parse_kind, Condition, Trigger, Watchpoint};
+
+/// Instructions listed by `u` when no count is given
+const DEFAULT_LIST_LEN: usize = 8;
+
+/// Bytes shown by `x` when no count is given
+const DEFAULT_DUMP_LEN: u16 = 64;
+
+const DUMP_PER_LINE: u16 = 16;
+
+const HELP: &str = "\
+s, step [N]          execute N instructions (default 1)
+n, next              execute one instruction, running called procedures to completion
+c, continue          run until a breakpoint or the program ends
+rs, reverse-step [N] undo the last N instructions (default 1)
+rewind N             step back until N instructions have executed
+last REG             step back to just before REG or FLAGS last changed
+b, break [ADDR]      set a breakpoint, or list breakpoints and conditions
+b ADDR if COND       set a breakpoint that only stops when COND holds
+b if COND            stop wherever COND becomes true, e.g. b if CX == 0 && ZF
+d, delete [ADDR|#N]  clear a breakpoint or condition N, or all of them
+watch ADDR [N] [r|w|rw]  stop when N bytes (default 1) are read and/or written (default w)
+watch                list watchpoints
+unwatch [N]          clear watchpoint N, or all of them
+r, regs              show registers and flags
+x ADDR [N]           examine N bytes of memory (default 64)
+w ADDR BYTE...       write bytes to memory
+set NAME VALUE       set a register (AX, AL, DS, IP, FLAGS, ...) or flag (ZF, CF, ...)
+u [N]                disassemble N instructions from CS:IP (default 8)
+bt, stack            show the call stack
+h, help              show this help
+q, quit              leave the debugger
+ADDR is SEG:OFFSET or an offset in CS; SEG can be a segment register.
+Addresses, bytes and values are hex; counts are decimal.
+Stepping back doesn't undo files written through DOS, nor edits made with w and set.
+COND compares registers and flags with ==, !=, <, <=, >, >=, !, && and ||; its numbers are decimal or 0x hex.";
+
+/// A CALL that hasn't returned yet
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    call: (u16, u16), // CS:IP of the CALL
+    sp: u16, // SP before the CALL pushed its return address
+}
+
+/// A breakpoint, which only stops when its condition holds if it has one
+#[derive(Debug, Clone)]
+struct Breakpoint {
+    addr: (u16, u16), // SEG:OFFSET it was set as
+    condition: Option<Condition>,
+}
+
+/// Why running stopped before the requested number of instructions
+enum Stop {
+    Done, // Reached what it was running to
+    Breakpoint,
+    Watchpoint(usize, Access),
+    Condition(usize),
+    Ended,
+    Error(String),
+}
+
+/// Interactive debugger driving a `Cpu` one instruction at a time
+pub struct Debugger {
+    pub cpu: Cpu,
+    breakpoints: BTreeMap<u32, Breakpoint>, // By physical address
+    conditions: Vec<Trigger>, // Checked after every instruction
+    watchpoints: Vec<Watchpoint>,
+    frames: Vec<Frame>,
+    saved_frames: Vec<(u64, Vec<Frame>)>, // Call stack before each CALL or RET, by instruction count
+}
+
+impl Debugger {
+    pub fn new(mut cpu: Cpu) -> Self {
+        cpu.record_history();
+        Debugger {
+            cpu,
+            breakpoints: BTreeMap::new(),
+            conditions: Vec::new(),
+            watchpoints: Vec::new(),
+            frames: Vec::new(),
+            saved_frames: Vec::new(),
+        }
+    }
+
+    /// Stops execution when an instruction touches `watchpoint`
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Stops execution when `condition` becomes true
+    pub fn add_condition(&mut self, condition: Condition) {
+        let trigger = Trigger::new(condition, &self.cpu.mem);
+        self.conditions.push(trigger);
+    }
+
+    /// Reads commands from `input` until it ends or `quit`
+    pub fn run(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "Type h for help.")?;
+        self.show_position(output)?;
+
+        let mut line = String::new();
+        loop {
+            write!(output, "(sim86) ")?;
+            output.flush()?;
+
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if !self.command(line.trim(), output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs one command line. Returns false to quit.
+    fn command(&mut self, line: &str, output: &mut impl Write) -> io::Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Ok(true);
+        };
+
+        match (name, args) {
+            ("s" | "step", _) => {
+                let count = match args.first() {
+                    Some(count) => match count.parse::<usize>() {
+                        Ok(count) => count,
+                        Err(_) => return error(output, &format!("Invalid count {}", count)),
+                    },
+                    None => 1,
+                };
+                for _ in 0..count {
+                    let (cs, ip) = self.position();
+                    let stop = match self.step() {
+                        Ok(inst) => {
+                            writeln!(output, "{}", self.format_inst(cs, ip, &inst))?;
+                            self.check()
+                        },
+                        Err(stop) => Some(stop),
+                    };
+                    if let Some(stop) = stop {
+                        self.report(stop, output)?;
+                        break;
+                    }
+                }
+                self.show_position(output)?;
+            },
+            ("n" | "next", _) => {
+                let stop = self.next();
+                self.report_stop(stop, output)?;
+            },
+            ("c" | "continue", _) => {
+                let stop = self.run_until(|_| false);
+                self.report_stop(Some(stop), output)?;
+            },
+            ("rs" | "reverse-step", _) => {
+                let count = match args.first() {
+                    Some(count) => match count.parse::<u64>() {
+                        Ok(count) => count,
+                        Err(_) => return error(output, &format!("Invalid count {}", count)),
+                    },
+                    None => 1,
+                };
+                if count > self.cpu.executed || !self.rewind(self.cpu.executed - count) {
+                    return error(output, "Not that many instructions in the history");
+                }
+                self.show_position(output)?;
+            },
+            ("rewind", [count]) => {
+                let Ok(count) = count.parse::<u64>() else {
+                    return error(output, &format!("Invalid count {}", count));
+                };
+                if !self.rewind(count) {
+                    return error(output, &format!("Instruction {} isn't in the history", count));
+                }
+                self.show_position(output)?;
+            },
+            ("last", [name]) => {
+                let name = name.to_uppercase();
+                if name != "FLAGS" && Memory::register_in(&self.cpu.mem.registers(), &name).is_none() {
+                    return error(output, &format!("Unknown register {}", name));
+                }
+                let Some(count) = self.cpu.last_write(&name) else {
+                    return error(output, &format!("{} hasn't changed in the history", name));
+                };
+                self.rewind(count);
+                self.show_position(output)?;
+            },
+            ("b" | "break", []) => {
+                for breakpoint in self.breakpoints.values() {
+                    let (seg, offset) = breakpoint.addr;
+                    match &breakpoint.condition {
+                        Some(condition) => writeln!(output, "{:04X}:{:04X} if {}", seg, offset, condition)?,
+                        None => writeln!(output, "{:04X}:{:04X}", seg, offset)?,
+                    }
+                }
+                for (idx, trigger) in self.conditions.iter().enumerate() {
+                    writeln!(output, "#{} if {}", idx + 1, trigger.condition)?;
+                }
+            },
+            ("b" | "break", ["if", ..]) => match args[1..].join(" ").parse::<Condition>() {
+                Ok(condition) => {
+                    self.add_condition(condition);
+                    writeln!(output, "Condition #{}", self.conditions.len())?;
+                },
+                Err(err) => return error(output, &err),
+            },
+            ("b" | "break", [addr, rest @ ..]) => {
+                let Some((seg, offset)) = self.parse_address(addr) else {
+                    return error(output, &format!("Invalid address {}", addr));
+                };
+                let condition = match rest {
+                    [] => None,
+                    ["if", ..] => match rest[1..].join(" ").parse::<Condition>() {
+                        Ok(condition) => Some(condition),
+                        Err(err) => return error(output, &err),
+                    },
+                    _ => return error(output, "Expected if COND after the address"),
+                };
+                self.breakpoints.insert(physical_address(seg, offset), Breakpoint { addr: (seg, offset), condition });
+                writeln!(output, "Breakpoint at {:04X}:{:04X}", seg, offset)?;
+            },
+            ("d" | "delete", []) => {
+                self.breakpoints.clear();
+                self.conditions.clear();
+            },
+            ("d" | "delete", [id]) if id.starts_with('#') => match id[1..].parse::<usize>() {
+                Ok(idx) if (1..=self.conditions.len()).contains(&idx) => {
+                    self.conditions.remove(idx - 1);
+                },
+                _ => return error(output, &format!("No condition {}", id)),
+            },
+            ("d" | "delete", [addr]) => match self.parse_address(addr) {
+                Some((seg, offset)) => {
+                    if self.breakpoints.remove(&physical_address(seg, offset)).is_none() {
+                        return error(output, &format!("No breakpoint at {:04X}:{:04X}", seg, offset));
+                    }
+                },
+                None => return error(output, &format!("Invalid address {}", addr)),
+            },
+            ("watch", []) => {
+                for (idx, watchpoint) in self.watchpoints.iter().enumerate() {
+                    writeln!(output, "{}  {}", idx + 1, watchpoint)?;
+                }
+            },
+            ("watch", [addr, rest @ ..]) => {
+                let Some((seg, offset)) = self.parse_address(addr) else {
+                    return error(output, &format!("Invalid address {}", addr));
+                };
+                let (len, kind) = match rest {
+                    [] => (Some(1), "w"),
+                    [arg] if arg.parse::<u32>().is_ok() => (arg.parse().ok(), "w"),
+                    [kind] => (Some(1), *kind),
+                    [len, kind] => (len.parse().ok(), *kind),
+                    _ => (None, ""),
+                };
+                let (Some(len @ 1..), Some((on_read, on_write))) = (len, parse_kind(kind)) else {
+                    return error(output, "Expected watch ADDR [N] [r|w|rw]");
+                };
+                let start = physical_address(seg, offset);
+                self.add_watchpoint(Watchpoint { start, end: start + len, on_read, on_write });
+                writeln!(output, "Watchpoint {}: {}", self.watchpoints.len(), self.watchpoints[self.watchpoints.len() - 1])?;
+            },
+            ("unwatch", []) => self.watchpoints.clear(),
+            ("unwatch", [idx]) => match idx.parse::<usize>() {
+                Ok(idx) if (1..=self.watchpoints.len()).contains(&idx) => {
+                    self.watchpoints.remove(idx - 1);
+                },
+                _ => return error(output, &format!("No watchpoint {}", idx)),
+            },
+            ("r" | "regs", _) => self.show_registers(output)?,
+            ("x", [addr, rest @ ..]) => {
+                let Some((seg, offset)) = self.parse_address(addr) else {
+                    return error(output, &format!("Invalid address {}", addr));
+                };
+                let count = match rest.first() {
+                    Some(count) => match count.parse::<u16>() {
+                        Ok(count) => count,
+                        Err(_) => return error(output, &format!("Invalid count {}", count)),
+                    },
+                    None => DEFAULT_DUMP_LEN,
+                };
+                self.dump(seg, offset, count, output)?;
+            },
+            ("w", [addr, bytes @ ..]) if !bytes.is_empty() => {
+                let Some((seg, offset)) = self.parse_address(addr) else {
+                    return error(output, &format!("Invalid address {}", addr));
+                };
+                let Some(bytes) = bytes.iter().map(|byte| u8::from_str_radix(byte.trim_start_matches("0x"), 16).ok()).collect::<Option<Vec<u8>>>() else {
+                    return error(output, "Invalid byte");
+                };
+                for (idx, byte) in bytes.iter().enumerate() {
+                    self.cpu.mem.write_byte(physical_address(seg, offset.wrapping_add(idx as u16)), *byte);
+                }
+            },
+            ("set", [name, value]) => {
+                let Some(value) = parse_hex(value) else {
+                    return error(output, &format!("Invalid value {}", value));
+                };
+                if !self.set(&name.to_uppercase(), value) {
+                    return error(output, &format!("Unknown register or flag {}", name));
+                }
+            },
+            ("u", _) => {
+                let count = match args.first() {
+                    Some(count) => match count.parse::<usize>() {
+                        Ok(count) => count,
+                        Err(_) => return error(output, &format!("Invalid count {}", count)),
+                    },
+                    None => DEFAULT_LIST_LEN,
+                };
+                self.list(count, output)?;
+            },
+            ("bt" | "stack", _) => self.show_stack(output)?,
+            ("h" | "help", _) => writeln!(output, "{}", HELP)?,
+            ("q" | "quit", _) => return Ok(false),
+            _ => return error(output, &format!("Unknown command {}; type h for help", line)),
+        }
+
+        Ok(true)
+    }
+
+    fn position(&self) -> (u16, u16) {
+        (self.cpu.mem.read_seg(SegReg::CS), self.cpu.mem.read_ip())
+    }
+
+    /// Executes one instruction, keeping track of calls and returns for the call stack
+    fn step(&mut self) -> Result<Instruction, Stop> {
+        if !self.cpu.running() {
+            return Err(Stop::Ended);
+        }
+
+        let call = self.position();
+        let sp = self.cpu.mem.read_reg(Reg::SP);
+        let executed = self.cpu.executed;
+        let inst = self.cpu.step().map_err(|err| Stop::Error(err.to_string()))?.inst;
+
+        match inst.opcode {
+            Opcode::CallDirect | Opcode::CallIndirect | Opcode::CallDirectFar | Opcode::CallIndirectFar => {
+                self.saved_frames.push((executed, self.frames.clone()));
+                self.frames.push(Frame { call, sp });
+            },
+            // Popping the return address takes SP back up past every frame that returned
+            Opcode::Ret | Opcode::RetImm | Opcode::RetFar | Opcode::RetFarImm => {
+                self.saved_frames.push((executed, self.frames.clone()));
+                let sp = self.cpu.mem.read_reg(Reg::SP);
+                self.frames.retain(|frame| frame.sp > sp);
+            },
+            _ => {},
+        }
+
+        Ok(inst)
+    }
+
+    /// Steps back until `executed` instructions have run, putting the call stack back too
+    fn rewind(&mut self, executed: u64) -> bool {
+        if !self.cpu.rewind_to(executed) {
+            return false;
+        }
+        while let Some((at, _)) = self.saved_frames.last() {
+            if *at < executed {
+                break;
+            }
+            if let Some((_, frames)) = self.saved_frames.pop() {
+                self.frames = frames;
+            }
+        }
+        for trigger in &mut self.conditions {
+            trigger.reset(&self.cpu.mem);
+        }
+        true
+    }
+
+    /// Whether the instruction just executed should stop execution: it touched a watched address,
+    /// made a condition true that wasn't, or led to a breakpoint
+    fn check(&mut self) -> Option<Stop> {
+        // Every condition's state is updated, so one that rose alongside another doesn't fire a step late
+        let mut rose = None;
+        for (idx, trigger) in self.conditions.iter_mut().enumerate() {
+            if trigger.fired(&self.cpu.mem) && rose.is_none() {
+                rose = Some(idx);
+            }
+        }
+
+        for (idx, watchpoint) in self.watchpoints.iter().enumerate() {
+            if let Some(access) = watchpoint.hit(&self.cpu.mem.accesses) {
+                return Some(Stop::Watchpoint(idx, *access));
+            }
+        }
+        if let Some(idx) = rose {
+            return Some(Stop::Condition(idx));
+        }
+
+        let (cs, ip) = self.position();
+        match self.breakpoints.get(&physical_address(cs, ip)) {
+            Some(Breakpoint { condition: Some(condition), .. }) if !condition.eval(&self.cpu.mem) => None,
+            Some(_) => Some(Stop::Breakpoint),
+            None => None,
+        }
+    }
+
+    /// Steps until `done` says so, something in `check` stops it or the program ends.
+    /// The last instruction is checked too, so a watchpoint it hits isn't lost.
+    fn run_until(&mut self, done: impl Fn(&Self) -> bool) -> Stop {
+        loop {
+            if let Err(stop) = self.step() {
+                return stop;
+            }
+            if let Some(stop) = self.check() {
+                return stop;
+            }
+            if done(self) {
+                return Stop::Done;
+            }
+        }
+    }
+
+    /// Steps one instruction, or over a whole call. Returns why it stopped early, if it did.
+    fn next(&mut self) -> Option<Stop> {
+        let inst = match self.cpu.peek() {
+            Ok(inst) => inst,
+            Err(err) => return Some(Stop::Error(err.to_string())),
+        };
+
+        match inst.opcode {
+            Opcode::CallDirect | Opcode::CallIndirect | Opcode::CallDirectFar | Opcode::CallIndirectFar => {
+                let (cs, ip) = self.position();
+                let ret = physical_address(cs, ip.wrapping_add(inst.len as u16));
+                let sp = self.cpu.mem.read_reg(Reg::SP);
+                // Back at the return address with the frame gone, which also catches recursion
+                match self.run_until(|dbg| {
+                    let (cs, ip) = dbg.position();
+                    physical_address(cs, ip) == ret && dbg.cpu.mem.read_reg(Reg::SP) >= sp
+                }) {
+                    Stop::Done => None,
+                    stop => Some(stop),
+                }
+            },
+            _ => match self.step() {
+                Ok(_) => self.check(),
+                Err(stop) => Some(stop),
+            },
+        }
+    }
+
+    fn report_stop(&self, stop: Option<Stop>, output: &mut impl Write) -> io::Result<()> {
+        if let Some(stop) = stop {
+            self.report(stop, output)?;
+        }
+        self.show_position(output)
+    }
+
+    fn report(&self, stop: Stop, output: &mut impl Write) -> io::Result<()> {
+        match stop {
+            Stop::Done => Ok(()),
+            Stop::Breakpoint => {
+                let (cs, ip) = self.position();
+                writeln!(output, "Breakpoint at {:04X}:{:04X}", cs, ip)
+            },
+            Stop::Watchpoint(idx, access) => {
+                let kind = if access.write { "write" } else { "read" };
+                let size = if access.wide { "word" } else { "byte" };
+                writeln!(output, "Watchpoint {} ({}): {} {} at {:05X}", idx + 1, self.watchpoints[idx], size, kind, access.addr)
+            },
+            Stop::Condition(idx) => writeln!(output, "Condition #{} holds: {}", idx + 1, self.conditions[idx].condition),
+            // Shown with the position
+            Stop::Ended => Ok(()),
+            Stop::Error(err) => writeln!(output, "{}", err),
+        }
+    }
+
+    /// Shows the instruction at CS:IP, which runs next, or that there isn't one
+    fn show_position(&self, output: &mut impl Write) -> io::Result<()> {
+        if !self.cpu.running() {
+            return match self.cpu.dos.exit_code {
+                Some(code) => writeln!(output, "Program exited with code {}", code),
+                None => writeln!(output, "Program has ended"),
+            };
+        }
+        let (cs, ip) = self.position();
+        match self.cpu.peek() {
+            Ok(inst) => writeln!(output, "=> {}", self.format_inst(cs, ip, &inst)),
+            Err(err) => writeln!(output, "=> {:04X}:{:04X}  {}", cs, ip, err),
+        }
+    }
+
+    /// `SEG:OFFSET  BYTES  INSTRUCTION`
+    fn format_inst(&self, cs: u16, ip: u16, inst: &Instruction) -> String {
+        let bytes: Vec<String> = self.cpu.mem.fetch(cs, ip, inst.len as u16).iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        // Branch targets as the address they go to, ready for a breakpoint
+        let text = match inst.dest {
+            Some(Operand::Rel(rel)) => format!("{} {:04X}:{:04X}", inst.str_val, cs, ip.wrapping_add(rel as u16)),
+            _ => inst.to_string(),
+        };
+        format!("{:04X}:{:04X}  {:<20}{}", cs, ip, bytes.join(" "), text)
+    }
+
+    fn show_registers(&self, output: &mut impl Write) -> io::Result<()> {
+        let regs: Vec<String> = Memory::loc_list().iter().zip(self.cpu.mem.registers())
+            .map(|(reg, val)| format!("{}={:04X}", reg, val))
+            .collect();
+        writeln!(output, "{}", regs.join(" "))?;
+        writeln!(output, "FLAGS={:04X} {}", self.cpu.mem.read_flags(), self.cpu.mem.flags_string())?;
+        writeln!(output, "Instructions executed: {}", self.cpu.executed)
+    }
+
+    fn dump(&self, seg: u16, offset: u16, count: u16, output: &mut impl Write) -> io::Result<()> {
+        let bytes = self.cpu.mem.fetch(seg, offset, count);
+        for (line, chunk) in bytes.chunks(usize::from(DUMP_PER_LINE)).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = chunk.iter()
+                .map(|&byte| match byte {
+                    0x20..=0x7E => char::from(byte),
+                    _ => '.',
+                })
+                .collect();
+            let line_offset = offset.wrapping_add(line as u16 * DUMP_PER_LINE);
+            writeln!(output, "{:04X}:{:04X}  {:<48}{}", seg, line_offset, hex.join(" "), text)?;
+        }
+        Ok(())
+    }
+
+    /// Sets a register or flag by name. Returns false if there's no such name.
+    fn set(&mut self, name: &str, value: u16) -> bool {
+        let mem = &mut self.cpu.mem;
+        if let Some(flag) = Flag::list().into_iter().find(|flag| format!("{}F", flag.letter()) == name) {
+            mem.set_flag(flag, value != 0);
+            return true;
+        }
+
+        match name {
+            "IP" => mem.write_ip(value),
+            "FLAGS" => mem.write_flags(value),
+            _ => match (name.parse::<SegReg>(), name.parse::<Reg>()) {
+                (Ok(seg), _) => mem.write_seg(seg, value),
+                (_, Ok(reg)) => mem.write_reg(reg, value),
+                _ => return false,
+            },
+        }
+        true
+    }
+
+    /// Lists instructions from CS:IP, marking the current one and breakpoints
+    fn list(&self, count: usize, output: &mut impl Write) -> io::Result<()> {
+        let (cs, mut ip) = self.position();
+        for idx in 0..count {
+            let bytes = self.cpu.mem.fetch(cs, ip, 16);
+            let marker = match (idx, self.breakpoints.contains_key(&physical_address(cs, ip))) {
+                (0, _) => "=> ",
+                (_, true) => " * ",
+                _ => "   ",
+            };
+            match decode(&bytes) {
+                Ok(inst) => {
+                    writeln!(output, "{}{}", marker, self.format_inst(cs, ip, &inst))?;
+                    ip = ip.wrapping_add(inst.len as u16);
+                },
+                Err(err) => {
+                    writeln!(output, "{}{:04X}:{:04X}  {}", marker, cs, ip, err.reason)?;
+                    return Ok(());
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Innermost first: where execution is, then every CALL that hasn't returned
+    fn show_stack(&self, output: &mut impl Write) -> io::Result<()> {
+        let (cs, ip) = self.position();
+        writeln!(output, "#0  {:04X}:{:04X}", cs, ip)?;
+        for (idx, frame) in self.frames.iter().rev().enumerate() {
+            writeln!(output, "#{}  {:04X}:{:04X}", idx + 1, frame.call.0, frame.call.1)?;
+        }
+        Ok(())
+    }
+
+    /// `SEG:OFFSET`, `SEGREG:OFFSET` or an offset in CS, all in hex
+    fn parse_address(&self, text: &str) -> Option<(u16, u16)> {
+        match text.split_once(':') {
+            Some((seg, offset)) => {
+                let seg = match seg.to_uppercase().parse::<SegReg>() {
+                    Ok(seg) => self.cpu.mem.read_seg(seg),
+                    Err(_) => parse_hex(seg)?,
+                };
+                Some((seg, parse_hex(offset)?))
+            },
+            None => Some((self.cpu.mem.read_seg(SegReg::CS), parse_hex(text)?)),
+        }
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.trim_start_matches("0x"), 16).ok()
+}
+
+fn error(output: &mut impl Write, message: &str) -> io::Result<bool> {
+    writeln!(output, "{}", message)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of a debugger session on `program` loaded as a .COM, given `commands` one per line
+    fn session(program: &[u8], commands: &str) -> String {
+        let mut cpu = Cpu::new();
+        cpu.load_com(program, "");
+        let mut output = Vec::new();
+        Debugger::new(cpu).run(&mut commands.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn condition_stops_only_when_it_becomes_true() {
+        // MOV AX, 1; NOP; NOP; MOV AX, 2; MOV AX, 1; HLT
+        let program = [0xB8, 0x01, 0x00, 0x90, 0x90, 0xB8, 0x02, 0x00, 0xB8, 0x01, 0x00, 0xF4];
+        let output = session(&program, "b if AX == 1\nc\nc\nq\n");
+        let stops: Vec<&str> = output.lines().filter(|line| line.starts_with("=> ")).collect();
+        assert_eq!(stops, ["=> 1000:0100  B8 01 00            MOV AX, 1", "=> 1000:0103  90                  XCHG AX, AX",
+            "=> 1000:010B  F4                  HLT"]);
+    }
+
+    #[test]
+    fn next_reports_watchpoint_on_its_last_instruction() {
+        // CALL $+4; HLT; RET, where the RET that ends the call reads the return address back
+        let program = [0xE8, 0x01, 0x00, 0xF4, 0xC3];
+        let output = session(&program, "watch SS:FFFC 2 r\nn\nq\n");
+        assert!(output.contains("word read at 1FFFC"), "{}", output);
+    }
+
+    #[test]
+    fn branch_targets_are_absolute() {
+        // CALL $+4; HLT; LOOP $+0
+        let program = [0xE8, 0x01, 0x00, 0xF4, 0xE2, 0xFE];
+        let output = session(&program, "u 3\nq\n");
+        assert!(output.contains("E8 01 00            CALL 1000:0104"), "{}", output);
+        assert!(output.contains("E2 FE               LOOP 1000:0104"), "{}", output);
+    }
+}
@@ -0,0 +1,521 @@
+use std::fmt::{self, Formatter};
+use super::instruction::*;
+use super::mem::SegReg;
+
+/// One field of an instruction encoding, in the order it appears in the instruction stream
+#[derive(Debug, Clone, Copy)]
+pub enum Field {
+    Bits(u8, u8),   // Literal opcode bits: (count, value)
+    D,              // 1 bit: REG is destination
+    W,              // 1 bit: wide (16 bits)
+    S,              // 1 bit: sign-extend 8-bit data
+    V,              // 1 bit: shift/rotate count in CL
+    Z,              // 1 bit: REP compares against ZF
+    Mod,            // 2 bits
+    Reg,            // 3 bits
+    Rm,             // 3 bits
+    Sr,             // 2 bits: segment register
+    Op,             // 3 bits: ImmToRm operation, or ESC opcode bits
+    Disp,           // DISP-LO/DISP-HI as selected by MOD and R/M
+    Addr,           // ADDR-LO ADDR-HI: direct address, same as MOD 00 R/M 110
+    Data,           // DATA-LO, and DATA-HI when W and not S
+    Data8,          // DATA-8: port or interrupt type
+    Data16,         // DATA-LO DATA-HI regardless of W
+    Inc8,           // IP-INC8
+    Inc16,          // IP-INC-LO IP-INC-HI
+    Far,            // IP-LO IP-HI CS-LO CS-HI
+    ImplD(bool),    // D implied by the opcode
+    ImplW(bool),    // W implied by the opcode
+    ImplReg(u8),    // REG implied by the opcode
+    ImplRm(u8),     // Register R/M implied by the opcode, sized by W
+    ImplRm16(u8),   // Register R/M implied by the opcode, always 16 bits
+}
+
+/// Encoding of an opcode as a sequence of fields
+#[derive(Debug)]
+pub struct Format {
+    pub opcode: Opcode,
+    pub fields: &'static [Field],
+}
+
+/// Field values pulled out of an instruction while matching it against a `Format`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Fields {
+    pub d: Option<bool>,
+    pub w: Option<bool>,
+    pub s: bool,
+    pub v: Option<bool>,
+    pub z: bool,
+    pub mode: Option<u8>,
+    pub reg: Option<u8>,
+    pub rm: Option<u8>,
+    pub rm_wide: bool,
+    pub sr: Option<u8>,
+    pub op: Option<u8>,
+    pub disp_lo: Option<u8>,
+    pub disp_hi: Option<u8>,
+    pub data: Option<u16>,
+    pub data8: Option<u8>,
+    pub inc: Option<u16>, // Sign-extended jump displacement
+    pub far: Option<(u16, u16)>, // CS, IP
+    pub segment: Option<SegReg>,
+    pub lock: bool,
+    pub rep: Option<bool>,
+}
+
+use Field::*;
+
+macro_rules! enc {
+    ($opcode:ident, [$($field:expr),* $(,)?]) => {
+        Format { opcode: Opcode::$opcode, fields: &[$($field),*] }
+    };
+}
+
+/// Every 8086 encoding, from the instruction encoding table in the 8086 manual
+pub static FORMATS: &[Format] = &[
+    // Data transfer
+    enc!(MovRmToReg,         [Bits(6, 0b100010), D, W, Mod, Reg, Rm, Disp]),
+    enc!(MovImmToRm,         [Bits(7, 0b1100011), W, Mod, Bits(3, 0b000), Rm, Disp, Data]),
+    enc!(MovImmToReg,        [Bits(4, 0b1011), W, Reg, Data, ImplD(true)]),
+    enc!(MovMemToAcc,        [Bits(7, 0b1010000), W, Addr, ImplReg(0b000), ImplD(true)]),
+    enc!(MovAccToMem,        [Bits(7, 0b1010001), W, Addr, ImplReg(0b000), ImplD(false)]),
+    enc!(MovRmToSeg,         [Bits(8, 0b10001110), Mod, Bits(1, 0b0), Sr, Rm, Disp, ImplW(true), ImplD(true)]),
+    enc!(MovSegToRm,         [Bits(8, 0b10001100), Mod, Bits(1, 0b0), Sr, Rm, Disp, ImplW(true), ImplD(false)]),
+    enc!(PushRm,             [Bits(8, 0b11111111), Mod, Bits(3, 0b110), Rm, Disp, ImplW(true)]),
+    enc!(PushReg,            [Bits(5, 0b01010), Reg, ImplW(true)]),
+    enc!(PushSeg,            [Bits(3, 0b000), Sr, Bits(3, 0b110), ImplW(true)]),
+    enc!(PopRm,              [Bits(8, 0b10001111), Mod, Bits(3, 0b000), Rm, Disp, ImplW(true)]),
+    enc!(PopReg,             [Bits(5, 0b01011), Reg, ImplW(true)]),
+    enc!(PopSeg,             [Bits(3, 0b000), Sr, Bits(3, 0b111), ImplW(true)]),
+    enc!(XchgRmWithReg,      [Bits(7, 0b1000011), W, Mod, Reg, Rm, Disp, ImplD(false)]),
+    enc!(XchgRegWithAcc,     [Bits(5, 0b10010), Reg, ImplW(true), ImplRm(0b000), ImplD(false)]),
+    enc!(InFixed,            [Bits(7, 0b1110010), W, Data8, ImplReg(0b000), ImplD(true)]),
+    enc!(InVariable,         [Bits(7, 0b1110110), W, ImplReg(0b000), ImplRm16(0b010), ImplD(true)]),
+    enc!(OutFixed,           [Bits(7, 0b1110011), W, Data8, ImplReg(0b000), ImplD(false)]),
+    enc!(OutVariable,        [Bits(7, 0b1110111), W, ImplReg(0b000), ImplRm16(0b010), ImplD(false)]),
+    enc!(Xlat,               [Bits(8, 0b11010111)]),
+    enc!(Lea,                [Bits(8, 0b10001101), Mod, Reg, Rm, Disp, ImplW(true), ImplD(true)]),
+    enc!(Lds,                [Bits(8, 0b11000101), Mod, Reg, Rm, Disp, ImplW(true), ImplD(true)]),
+    enc!(Les,                [Bits(8, 0b11000100), Mod, Reg, Rm, Disp, ImplW(true), ImplD(true)]),
+    enc!(Lahf,               [Bits(8, 0b10011111)]),
+    enc!(Sahf,               [Bits(8, 0b10011110)]),
+    enc!(Pushf,              [Bits(8, 0b10011100)]),
+    enc!(Popf,               [Bits(8, 0b10011101)]),
+
+    // Arithmetic
+    enc!(AddRmAndReg,        [Bits(6, 0b000000), D, W, Mod, Reg, Rm, Disp]),
+    enc!(ImmToRm,            [Bits(6, 0b100000), S, W, Mod, Op, Rm, Disp, Data]),
+    enc!(AddImmToAcc,        [Bits(7, 0b0000010), W, Data, ImplReg(0b000), ImplD(true)]),
+    enc!(AdcRmAndReg,        [Bits(6, 0b000100), D, W, Mod, Reg, Rm, Disp]),
+    enc!(AdcImmToAcc,        [Bits(7, 0b0001010), W, Data, ImplReg(0b000), ImplD(true)]),
+    enc!(IncRm,              [Bits(7, 0b1111111), W, Mod, Bits(3, 0b000), Rm, Disp]),
+    enc!(IncReg,             [Bits(5, 0b01000), Reg, ImplW(true)]),
+    enc!(Aaa,                [Bits(8, 0b00110111)]),
+    enc!(Daa,                [Bits(8, 0b00100111)]),
+    enc!(SubRmAndReg,        [Bits(6, 0b001010), D, W, Mod, Reg, Rm, Disp]),
+    enc!(SubImmFromAcc,      [Bits(7, 0b0010110), W, Data, ImplReg(0b000), ImplD(true)]),
+    enc!(SbbRmAndReg,        [Bits(6, 0b000110), D, W, Mod, Reg, Rm, Disp]),
+    enc!(SbbImmFromAcc,      [Bits(7, 0b0001110), W, Data, ImplReg(0b000), ImplD(true)]),
+    enc!(DecRm,              [Bits(7, 0b1111111), W, Mod, Bits(3, 0b001), Rm, Disp]),
+    enc!(DecReg,             [Bits(5, 0b01001), Reg, ImplW(true)]),
+    enc!(Neg,                [Bits(7, 0b1111011), W, Mod, Bits(3, 0b011), Rm, Disp]),
+    enc!(CmpRmAndReg,        [Bits(6, 0b001110), D, W, Mod, Reg, Rm, Disp]),
+    enc!(CmpImmToAcc,        [Bits(7, 0b0011110), W, Data, ImplReg(0b000), ImplD(true)]),
+    enc!(Aas,                [Bits(8, 0b00111111)]),
+    enc!(Das,                [Bits(8, 0b00101111)]),
+    enc!(Mul,                [Bits(7, 0b1111011), W, Mod, Bits(3, 0b100), Rm, Disp]),
+    enc!(Imul,               [Bits(7, 0b1111011), W, Mod, Bits(3, 0b101), Rm, Disp]),
+    enc!(Aam,                [Bits(8, 0b11010100), Bits(8, 0b00001010)]),
+    enc!(Div,                [Bits(7, 0b1111011), W, Mod, Bits(3, 0b110), Rm, Disp]),
+    enc!(Idiv,               [Bits(7, 0b1111011), W, Mod, Bits(3, 0b111), Rm, Disp]),
+    enc!(Aad,                [Bits(8, 0b11010101), Bits(8, 0b00001010)]),
+    enc!(Cbw,                [Bits(8, 0b10011000)]),
+    enc!(Cwd,                [Bits(8, 0b10011001)]),
+
+    // Logic
+    enc!(Not,                [Bits(7, 0b1111011), W, Mod, Bits(3, 0b010), Rm, Disp]),
+    enc!(Shl,                [Bits(6, 0b110100), V, W, Mod, Bits(3, 0b100), Rm, Disp]),
+    enc!(Shr,                [Bits(6, 0b110100), V, W, Mod, Bits(3, 0b101), Rm, Disp]),
+    enc!(Sar,                [Bits(6, 0b110100), V, W, Mod, Bits(3, 0b111), Rm, Disp]),
+    enc!(Rol,                [Bits(6, 0b110100), V, W, Mod, Bits(3, 0b000), Rm, Disp]),
+    enc!(Ror,                [Bits(6, 0b110100), V, W, Mod, Bits(3, 0b001), Rm, Disp]),
+    enc!(Rcl,                [Bits(6, 0b110100), V, W, Mod, Bits(3, 0b010), Rm, Disp]),
+    enc!(Rcr,                [Bits(6, 0b110100), V, W, Mod, Bits(3, 0b011), Rm, Disp]),
+    enc!(AndRmAndReg,        [Bits(6, 0b001000), D, W, Mod, Reg, Rm, Disp]),
+    enc!(AndImmToAcc,        [Bits(7, 0b0010010), W, Data, ImplReg(0b000), ImplD(true)]),
+    enc!(TestRmAndReg,       [Bits(7, 0b1000010), W, Mod, Reg, Rm, Disp, ImplD(false)]),
+    enc!(TestImmToRm,        [Bits(7, 0b1111011), W, Mod, Bits(3, 0b000), Rm, Disp, Data]),
+    enc!(TestImmToAcc,       [Bits(7, 0b1010100), W, Data, ImplReg(0b000), ImplD(true)]),
+    enc!(OrRmAndReg,         [Bits(6, 0b000010), D, W, Mod, Reg, Rm, Disp]),
+    enc!(OrImmToAcc,         [Bits(7, 0b0000110), W, Data, ImplReg(0b000), ImplD(true)]),
+    enc!(XorRmAndReg,        [Bits(6, 0b001100), D, W, Mod, Reg, Rm, Disp]),
+    enc!(XorImmToAcc,        [Bits(7, 0b0011010), W, Data, ImplReg(0b000), ImplD(true)]),
+
+    // String manipulation
+    enc!(Rep,                [Bits(7, 0b1111001), Z]),
+    enc!(Movs,               [Bits(7, 0b1010010), W]),
+    enc!(Cmps,               [Bits(7, 0b1010011), W]),
+    enc!(Scas,               [Bits(7, 0b1010111), W]),
+    enc!(Lods,               [Bits(7, 0b1010110), W]),
+    enc!(Stos,               [Bits(7, 0b1010101), W]),
+
+    // Control transfer
+    enc!(CallDirect,         [Bits(8, 0b11101000), Inc16]),
+    enc!(CallIndirect,       [Bits(8, 0b11111111), Mod, Bits(3, 0b010), Rm, Disp, ImplW(true)]),
+    enc!(CallDirectFar,      [Bits(8, 0b10011010), Far]),
+    enc!(CallIndirectFar,    [Bits(8, 0b11111111), Mod, Bits(3, 0b011), Rm, Disp, ImplW(true)]),
+    enc!(JmpDirect,          [Bits(8, 0b11101001), Inc16]),
+    enc!(JmpShort,           [Bits(8, 0b11101011), Inc8]),
+    enc!(JmpIndirect,        [Bits(8, 0b11111111), Mod, Bits(3, 0b100), Rm, Disp, ImplW(true)]),
+    enc!(JmpDirectFar,       [Bits(8, 0b11101010), Far]),
+    enc!(JmpIndirectFar,     [Bits(8, 0b11111111), Mod, Bits(3, 0b101), Rm, Disp, ImplW(true)]),
+    enc!(Ret,                [Bits(8, 0b11000011)]),
+    enc!(RetImm,             [Bits(8, 0b11000010), Data16, ImplW(true)]),
+    enc!(RetFar,             [Bits(8, 0b11001011)]),
+    enc!(RetFarImm,          [Bits(8, 0b11001010), Data16, ImplW(true)]),
+    enc!(JmpEqual,           [Bits(8, 0b01110100), Inc8]),
+    enc!(JmpLess,            [Bits(8, 0b01111100), Inc8]),
+    enc!(JmpLessOrEqual,     [Bits(8, 0b01111110), Inc8]),
+    enc!(JmpBelow,           [Bits(8, 0b01110010), Inc8]),
+    enc!(JmpBelowOrEqual,    [Bits(8, 0b01110110), Inc8]),
+    enc!(JmpParity,          [Bits(8, 0b01111010), Inc8]),
+    enc!(JmpOverflow,        [Bits(8, 0b01110000), Inc8]),
+    enc!(JmpSign,            [Bits(8, 0b01111000), Inc8]),
+    enc!(JmpNotEqual,        [Bits(8, 0b01110101), Inc8]),
+    enc!(JmpNotLess,         [Bits(8, 0b01111101), Inc8]),
+    enc!(JmpNotLessOrEqual,  [Bits(8, 0b01111111), Inc8]),
+    enc!(JmpNotBelow,        [Bits(8, 0b01110011), Inc8]),
+    enc!(JmpNotBelowOrEqual, [Bits(8, 0b01110111), Inc8]),
+    enc!(JmpNotParity,       [Bits(8, 0b01111011), Inc8]),
+    enc!(JmpNotOverflow,     [Bits(8, 0b01110001), Inc8]),
+    enc!(JmpOnNotSign,       [Bits(8, 0b01111001), Inc8]),
+    enc!(Loop,               [Bits(8, 0b11100010), Inc8]),
+    enc!(LoopZero,           [Bits(8, 0b11100001), Inc8]),
+    enc!(LoopNotZero,        [Bits(8, 0b11100000), Inc8]),
+    enc!(JmpCXZero,          [Bits(8, 0b11100011), Inc8]),
+    enc!(Int,                [Bits(8, 0b11001101), Data8]),
+    enc!(Int3,               [Bits(8, 0b11001100)]),
+    enc!(Into,               [Bits(8, 0b11001110)]),
+    enc!(Iret,               [Bits(8, 0b11001111)]),
+
+    // Processor control
+    enc!(Clc,                [Bits(8, 0b11111000)]),
+    enc!(Cmc,                [Bits(8, 0b11110101)]),
+    enc!(Stc,                [Bits(8, 0b11111001)]),
+    enc!(Cld,                [Bits(8, 0b11111100)]),
+    enc!(Std,                [Bits(8, 0b11111101)]),
+    enc!(Cli,                [Bits(8, 0b11111010)]),
+    enc!(Sti,                [Bits(8, 0b11111011)]),
+    enc!(Hlt,                [Bits(8, 0b11110100)]),
+    enc!(Wait,               [Bits(8, 0b10011011)]),
+    enc!(Esc,                [Bits(5, 0b11011), Op, Mod, Op, Rm, Disp]),
+    enc!(Lock,               [Bits(8, 0b11110000)]),
+    enc!(Segment,            [Bits(3, 0b001), Sr, Bits(3, 0b110)]),
+];
+
+/// Reads fields MSB first out of an instruction's bytes
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u8) -> Result<u8, DecodeReason> {
+        let byte = *self.bytes.get(self.pos).ok_or(DecodeReason::Truncated)?;
+        let val = (byte >> (8 - self.bit - count)) & (((1u16 << count) - 1) as u8);
+
+        self.bit += count;
+        if self.bit == 8 {
+            self.pos += 1;
+            self.bit = 0;
+        }
+
+        Ok(val)
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeReason> {
+        self.bits(8)
+    }
+
+    fn word(&mut self) -> Result<u16, DecodeReason> {
+        let lo = self.byte()?;
+        let hi = self.byte()?;
+        Ok(u16::from(hi) << 8 | u16::from(lo))
+    }
+}
+
+/// Matches `bytes` against a format, returning the decoded fields and the instruction length
+fn decode_format(format: &Format, bytes: &[u8]) -> Result<(Fields, usize), DecodeReason> {
+    let mut reader = BitReader { bytes, pos: 0, bit: 0 };
+    let mut fields = Fields::default();
+
+    for field in format.fields {
+        match *field {
+            Bits(count, value) => {
+                // Opcode bits past the first byte select an operation within the opcode
+                let reason = match reader.pos {
+                    0 => DecodeReason::UnknownOpcode,
+                    _ => DecodeReason::InvalidModReg,
+                };
+                if reader.bits(count)? != value {
+                    return Err(reason);
+                }
+            },
+            D => fields.d = Some(reader.bits(1)? != 0),
+            W => fields.w = Some(reader.bits(1)? != 0),
+            S => fields.s = reader.bits(1)? != 0,
+            V => fields.v = Some(reader.bits(1)? != 0),
+            Z => fields.z = reader.bits(1)? != 0,
+            Mod => fields.mode = Some(reader.bits(2)?),
+            Reg => fields.reg = Some(reader.bits(3)?),
+            Rm => fields.rm = Some(reader.bits(3)?),
+            Sr => fields.sr = Some(reader.bits(2)?),
+            Op => {
+                // ESC splits its opcode across two fields
+                let bits = reader.bits(3)?;
+                fields.op = Some(fields.op.map_or(bits, |op| op << 3 | bits));
+            },
+            Disp => {
+                match (fields.mode, fields.rm) {
+                    (Some(0b00), Some(0b110)) | (Some(0b10), _) => {
+                        fields.disp_lo = Some(reader.byte()?);
+                        fields.disp_hi = Some(reader.byte()?);
+                    },
+                    (Some(0b01), _) => fields.disp_lo = Some(reader.byte()?),
+                    _ => {},
+                }
+            },
+            Addr => {
+                fields.mode = Some(0b00);
+                fields.rm = Some(0b110);
+                fields.disp_lo = Some(reader.byte()?);
+                fields.disp_hi = Some(reader.byte()?);
+            },
+            Data => {
+                let data = match (fields.s, fields.w.unwrap_or(false)) {
+                    (false, true) => reader.word()?,
+                    // Sign-extend 8-bit data to 16 bits
+                    (true, true) => reader.byte()? as i8 as u16,
+                    (_, false) => u16::from(reader.byte()?),
+                };
+                fields.data = Some(data);
+            },
+            Data8 => fields.data8 = Some(reader.byte()?),
+            Data16 => fields.data = Some(reader.word()?),
+            Inc8 => fields.inc = Some(reader.byte()? as i8 as u16),
+            Inc16 => fields.inc = Some(reader.word()?),
+            Far => {
+                let ip = reader.word()?;
+                let cs = reader.word()?;
+                fields.far = Some((cs, ip));
+            },
+            ImplD(d) => fields.d = Some(d),
+            ImplW(w) => fields.w = Some(w),
+            ImplReg(reg) => fields.reg = Some(reg),
+            ImplRm(rm) => {
+                fields.mode = Some(0b11);
+                fields.rm = Some(rm);
+            },
+            ImplRm16(rm) => {
+                fields.mode = Some(0b11);
+                fields.rm = Some(rm);
+                fields.rm_wide = true;
+            },
+        }
+    }
+
+    // Operands that must be in memory
+    let mem_only = matches!(format.opcode, Opcode::Lea | Opcode::Lds | Opcode::Les | Opcode::CallIndirectFar | Opcode::JmpIndirectFar);
+    if mem_only && fields.mode == Some(0b11) {
+        return Err(DecodeReason::InvalidModReg);
+    }
+
+    Ok((fields, reader.pos))
+}
+
+/// Why a sequence of bytes couldn't be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeReason {
+    UnknownOpcode,
+    InvalidModReg, // Opcode is valid but its MOD/REG/R/M bits aren't
+    Truncated, // Input ends partway through the instruction
+}
+
+impl fmt::Display for DecodeReason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownOpcode => write!(f, "unknown opcode"),
+            Self::InvalidModReg => write!(f, "invalid MOD/REG combination"),
+            Self::Truncated => write!(f, "truncated instruction"),
+        }
+    }
+}
+
+/// Bytes that don't form a valid instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub offset: usize, // Where the instruction starts, including prefixes
+    pub bytes: Vec<u8>,
+    pub reason: DecodeReason,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} at offset 0x{:04x}:", self.reason, self.offset)?;
+        for byte in &self.bytes {
+            write!(f, " {:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes the instruction at the start of `buffer`, including any prefixes.
+/// Errors report an offset of 0; callers that know where `buffer` came from can adjust it.
+pub fn decode(buffer: &[u8]) -> Result<Instruction, DecodeError> {
+    let mut prefixes = Fields::default();
+    let mut pos = 0;
+
+    loop {
+        let bytes = &buffer[pos..];
+
+        // Take the first matching format. Failing that, report the most specific reason any format gave.
+        let mut reason = DecodeReason::UnknownOpcode;
+        let mut matched = None;
+        for format in FORMATS {
+            match decode_format(format, bytes) {
+                Ok((fields, len)) => {
+                    matched = Some((format, fields, len));
+                    break;
+                },
+                Err(DecodeReason::UnknownOpcode) => {},
+                Err(DecodeReason::InvalidModReg) if reason == DecodeReason::Truncated => {},
+                Err(err) => reason = err,
+            }
+        }
+
+        let Some((format, fields, len)) = matched else {
+            // Six bytes is the longest instruction without prefixes
+            let end = buffer.len().min(pos + 6);
+            return Err(DecodeError { offset: 0, bytes: buffer[..end].to_vec(), reason });
+        };
+
+        match format.opcode {
+            // Prefixes apply to the instruction that follows them
+            Opcode::Segment => prefixes.segment = fields.sr.map(SegReg::from),
+            Opcode::Lock => prefixes.lock = true,
+            Opcode::Rep => prefixes.rep = Some(fields.z),
+            opcode => {
+                let fields = Fields {
+                    segment: prefixes.segment,
+                    lock: prefixes.lock,
+                    rep: prefixes.rep,
+                    ..fields
+                };
+                return Ok(Instruction::new(opcode, &fields, &buffer[..pos + len]));
+            }
+        }
+
+        pos += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operand::Operand;
+
+    /// Each listing with what the decoder before the encoding table printed for it, one instruction per line
+    const LISTINGS: [(&[u8], &str); 4] = [
+        (include_bytes!("../data/listing_0041_add_sub_cmp_jnz"), include_str!("../data/listing_0041_add_sub_cmp_jnz.decoded")),
+        (include_bytes!("../data/listing_0043_immediate_movs"), include_str!("../data/listing_0043_immediate_movs.decoded")),
+        (include_bytes!("../data/listing_0044_register_movs"), include_str!("../data/listing_0044_register_movs.decoded")),
+        (include_bytes!("../data/listing_0045_challenge_register_movs"), include_str!("../data/listing_0045_challenge_register_movs.decoded")),
+    ];
+
+    fn decode_all(mut buffer: &[u8]) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        while !buffer.is_empty() {
+            let inst = decode(buffer).unwrap_or_else(|err| panic!("{}", err));
+            buffer = &buffer[inst.len..];
+            instructions.push(inst);
+        }
+        instructions
+    }
+
+    /// The old decoder printed relative jumps as `label, DISP`, with DISP from the end of the instruction
+    fn old_format(inst: &Instruction) -> String {
+        match inst.dest {
+            Some(Operand::Rel(rel)) => format!("{} label, {}", inst.str_val, rel - inst.len as i16),
+            _ => inst.to_string(),
+        }
+    }
+
+    #[test]
+    fn listings_decode_as_before() {
+        for (buffer, expected) in LISTINGS {
+            let decoded: Vec<String> = decode_all(buffer).iter().map(old_format).collect();
+            assert_eq!(decoded, expected.lines().collect::<Vec<&str>>());
+        }
+    }
+
+    #[test]
+    fn segment_override_prefix() {
+        let inst = decode(&[0x26, 0x8B, 0x07]).unwrap();
+        assert_eq!(inst.len, 3);
+        assert_eq!(inst.segment, Some(SegReg::ES));
+        assert_eq!(inst.to_string(), "MOV AX, ES:[BX]");
+    }
+
+    #[test]
+    fn lock_and_rep_prefixes() {
+        let inst = decode(&[0xF0, 0x86, 0x07]).unwrap();
+        assert!(inst.lock);
+        assert_eq!(inst.to_string(), "LOCK XCHG [BX], AL");
+
+        let inst = decode(&[0xF3, 0xA4]).unwrap();
+        assert_eq!((inst.len, inst.rep), (2, Some(true)));
+        let inst = decode(&[0xF2, 0xAE]).unwrap();
+        assert_eq!((inst.len, inst.rep), (2, Some(false)));
+    }
+
+    #[test]
+    fn prefixes_combine() {
+        let inst = decode(&[0xF0, 0x2E, 0xFF, 0x07, 0x90]).unwrap();
+        assert_eq!(inst.len, 4);
+        assert!(inst.lock);
+        assert_eq!(inst.segment, Some(SegReg::CS));
+        assert_eq!(inst.opcode, Opcode::IncRm);
+    }
+
+    #[test]
+    fn truncated_instructions() {
+        let err = decode(&[0xB8, 0x01]).unwrap_err();
+        assert_eq!(err, DecodeError { offset: 0, bytes: vec![0xB8, 0x01], reason: DecodeReason::Truncated });
+        assert_eq!(err.to_string(), "truncated instruction at offset 0x0000: B8 01");
+
+        // Cut off in the displacement, and after a prefix
+        assert_eq!(decode(&[0x8B, 0x87, 0x34]).unwrap_err().reason, DecodeReason::Truncated);
+        let err = decode(&[0x26]).unwrap_err();
+        assert_eq!((err.bytes, err.reason), (vec![0x26], DecodeReason::Truncated));
+    }
+
+    #[test]
+    fn invalid_encodings() {
+        let err = decode(&[0x60, 0x00]).unwrap_err();
+        assert_eq!(err.reason, DecodeReason::UnknownOpcode);
+
+        // LEA needs a memory operand, and FF /7 isn't an instruction
+        assert_eq!(decode(&[0x8D, 0xC0]).unwrap_err().reason, DecodeReason::InvalidModReg);
+        let err = decode(&[0xFF, 0xF8, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90]).unwrap_err();
+        assert_eq!(err.reason, DecodeReason::InvalidModReg);
+        assert_eq!(err.bytes.len(), 6);
+    }
+    #[test]
+    fn errors_report_where_the_instruction_starts() {
+        let (_, errors) = crate::disasm::disassemble(&[0x90, 0x26, 0xB8, 0x01], 0);
+        assert_eq!(errors[0], DecodeError { offset: 1, bytes: vec![0x26, 0xB8, 0x01], reason: DecodeReason::Truncated });
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, BTreeSet};
+use super::decode::{decode, DecodeError};
+use super::instruction::*;
+use super::mem::{EffectiveAddress, Reg};
+use super::operand::*;
+
+/// Bytes per `db` line for undecoded data
+const DB_PER_LINE: usize = 16;
+
+/// Prefix bytes: segment overrides, LOCK, REPNE and REP
+const PREFIXES: [u8; 7] = [0x26, 0x2E, 0x36, 0x3E, 0xF0, 0xF2, 0xF3];
+
+/// Decoded image: instructions by offset. Offsets without an entry are emitted as data.
+type Listing = BTreeMap<usize, Instruction>;
+
+/// Disassembles `buffer` into NASM source that reassembles to the same bytes, decoding it
+/// front to back from `entry`. Bytes before `entry`, and bytes that can't be decoded, are
+/// emitted as `db`; the decode errors are returned.
+pub fn disassemble(buffer: &[u8], entry: usize) -> (String, Vec<DecodeError>) {
+    let mut listing = Listing::new();
+    let mut errors = Vec::new();
+    let mut index = entry;
+
+    while index < buffer.len() {
+        match decode(&buffer[index..]) {
+            Ok(inst) => {
+                let len = inst.len;
+                listing.insert(index, inst);
+                index += len;
+            },
+            Err(err) => {
+                errors.push(DecodeError { offset: index, ..err });
+                index += 1;
+            }
+        }
+    }
+
+    (write_listing(buffer, &listing), errors)
+}
+
+/// Disassembles `buffer` by following control flow from `entry`. Only bytes reachable through
+/// jumps, loops, calls and fall-through are decoded; the rest are emitted as `db`.
+pub fn disassemble_recursive(buffer: &[u8], entry: usize) -> (String, Vec<DecodeError>) {
+    let mut listing = Listing::new();
+    let mut errors = Vec::new();
+    let mut is_code = vec![false; buffer.len()];
+    let mut pending = vec![entry];
+
+    while let Some(offset) = pending.pop() {
+        if offset >= buffer.len() || is_code[offset] {
+            continue;
+        }
+
+        let inst = match decode(&buffer[offset..]) {
+            Ok(inst) => inst,
+            Err(err) => {
+                errors.push(DecodeError { offset, ..err });
+                continue;
+            }
+        };
+
+        // Don't decode over bytes already claimed by another instruction
+        let end = offset + inst.len;
+        if is_code[offset..end].iter().any(|&code| code) {
+            continue;
+        }
+        is_code[offset..end].fill(true);
+
+        if let Some(target) = jump_target(&inst, offset) {
+            pending.push(target);
+        }
+        if falls_through(inst.opcode) {
+            pending.push(end);
+        }
+
+        listing.insert(offset, inst);
+    }
+
+    errors.sort_by_key(|err| err.offset);
+    (write_listing(buffer, &listing), errors)
+}
+
+/// Whether execution can continue with the next instruction
+fn falls_through(opcode: Opcode) -> bool {
+    !matches!(opcode,
+        Opcode::JmpDirect | Opcode::JmpShort | Opcode::JmpIndirect | Opcode::JmpDirectFar | Opcode::JmpIndirectFar |
+        Opcode::Ret | Opcode::RetImm | Opcode::RetFar | Opcode::RetFarImm | Opcode::Iret | Opcode::Hlt)
+}
+
+/// Writes decoded instructions as NASM source, with labels on jump targets and `db` lines for everything else
+fn write_listing(buffer: &[u8], listing: &Listing) -> String {
+    let labels = labels(listing);
+
+    let mut output = String::from("bits 16\n\n");
+    let mut data: Vec<u8> = Vec::new();
+    let mut index = 0;
+
+    while index < buffer.len() {
+        let Some(inst) = listing.get(&index) else {
+            data.push(buffer[index]);
+            index += 1;
+            continue;
+        };
+
+        if !data.is_empty() {
+            for chunk in data.chunks(DB_PER_LINE) {
+                output.push_str(&db(chunk));
+                output.push('\n');
+            }
+            data.clear();
+        }
+
+        if let Some(label) = labels.get(&index) {
+            output.push_str(&format!("label_{}:\n", label));
+        }
+        output.push_str(&nasm(inst, &buffer[index..index + inst.len], index, &labels));
+        output.push('\n');
+        index += inst.len;
+    }
+
+    for chunk in data.chunks(DB_PER_LINE) {
+        output.push_str(&db(chunk));
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Offset a relative jump lands on, if any
+pub fn jump_target(inst: &Instruction, offset: usize) -> Option<usize> {
+    match inst.dest {
+        Some(Operand::Rel(rel)) => usize::try_from(offset as isize + rel as isize).ok(),
+        _ => None,
+    }
+}
+
+/// Numbers every jump target that starts a decoded instruction, in address order
+fn labels(listing: &Listing) -> BTreeMap<usize, usize> {
+    let targets: BTreeSet<usize> = listing.iter()
+        .filter_map(|(offset, inst)| jump_target(inst, *offset))
+        .filter(|target| listing.contains_key(target))
+        .collect();
+
+    targets.into_iter().enumerate().map(|(label, target)| (target, label)).collect()
+}
+
+/// `db` line holding raw bytes
+fn db(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+    format!("db {}", bytes.join(", "))
+}
+
+/// One instruction at `offset` as NASM source. `bytes` are the instruction's own bytes.
+/// Jumps to an offset in `labels` use its label, others fall back to `$+N`.
+pub fn nasm(inst: &Instruction, bytes: &[u8], offset: usize, labels: &BTreeMap<usize, usize>) -> String {
+    // NASM has no ESC mnemonic
+    if inst.opcode == Opcode::Esc {
+        return db(bytes);
+    }
+    if !canonical(inst, bytes) {
+        return format!("{} ; {}", db(bytes), inst);
+    }
+
+    let mut str_val = match inst.opcode {
+        Opcode::JmpShort => format!("{} SHORT", inst.str_val),
+        Opcode::JmpDirect => format!("{} NEAR", inst.str_val),
+        _ => inst.str_val.clone(),
+    };
+
+    // Segment override without a memory operand to attach it to, e.g. on string instructions
+    let has_mem = [inst.dest, inst.source].iter().flatten().any(Operand::is_mem);
+    if let (Some(seg), false) = (inst.segment, has_mem) {
+        str_val = format!("{} {}", seg, str_val);
+    }
+
+    // NASM puts the first of two XCHG registers in REG, where the decoder has it second
+    let order = match (inst.opcode, inst.dest, inst.source) {
+        (Opcode::XchgRmWithReg, Some(Operand::Reg(_)), Some(Operand::Reg(_))) => [inst.source, inst.dest],
+        _ => [inst.dest, inst.source],
+    };
+    let operands: Vec<String> = order.iter().flatten()
+        .map(|operand| match jump_target(inst, offset).and_then(|target| labels.get(&target)) {
+            Some(label) => format!("label_{}", label),
+            None => nasm_operand(inst, operand),
+        })
+        .collect();
+
+    match operands.is_empty() {
+        true => str_val,
+        false => format!("{} {}", str_val, operands.join(", ")),
+    }
+}
+
+/// Whether NASM picks this encoding for the instruction. Where the 8086 has more than one way to
+/// encode an instruction, NASM always assembles the same one, so the others have to be emitted as `db`.
+fn canonical(inst: &Instruction, bytes: &[u8]) -> bool {
+    // NASM orders prefixes its own way and never repeats one
+    let prefixes = bytes.iter().take_while(|byte| PREFIXES.contains(byte)).count();
+    if prefixes > 1 {
+        return false;
+    }
+    // A REP prefix on anything but a string instruction has no mnemonic to carry it
+    if inst.rep.is_some() && !is_string(inst.opcode) {
+        return false;
+    }
+
+    let reg = |operand: Option<Operand>| matches!(operand, Some(Operand::Reg(_)));
+    let acc = |operand: Option<Operand>| matches!(operand, Some(Operand::Reg(Reg::AL | Reg::AX)));
+    let direct = |operand: Option<Operand>| matches!(operand, Some(Operand::Mem(mem_op)) if mem_op.ea == EffectiveAddress::Direct);
+    let reg_reg = reg(inst.dest) && reg(inst.source);
+
+    match inst.opcode {
+        // Register to register goes in the R/M-is-destination direction
+        Opcode::AddRmAndReg | Opcode::AdcRmAndReg | Opcode::SubRmAndReg | Opcode::SbbRmAndReg |
+        Opcode::CmpRmAndReg | Opcode::AndRmAndReg | Opcode::OrRmAndReg | Opcode::XorRmAndReg => !(reg_reg && inst.d),
+        // The accumulator has shorter forms for direct addresses
+        Opcode::MovRmToReg => !(reg_reg && inst.d || acc(inst.dest) && direct(inst.source) || direct(inst.dest) && acc(inst.source)),
+        // AX has one-byte XCHG forms
+        Opcode::XchgRmWithReg => !(reg_reg && inst.w && (acc(inst.dest) || acc(inst.source))),
+        // Which register goes in REG isn't up to the source, unless they're the same one
+        Opcode::TestRmAndReg => !reg_reg || inst.dest == inst.source,
+        // 82 repeats 80, and the accumulator has its own forms
+        Opcode::ImmToRm => bytes[prefixes] != 0x82 && !(acc(inst.dest) && bytes[prefixes] != 0x83),
+        Opcode::TestImmToRm => !acc(inst.dest),
+        // Registers have their own one-byte or immediate forms
+        Opcode::MovImmToRm | Opcode::PushRm | Opcode::PopRm => !reg(inst.dest),
+        Opcode::IncRm | Opcode::DecRm => !(inst.w && reg(inst.dest)),
+        _ => true,
+    }
+}
+
+fn nasm_operand(inst: &Instruction, operand: &Operand) -> String {
+    match operand {
+        Operand::Imm(imm) => {
+            // NASM would pick the sign-extended 8-bit form for small immediates
+            let sign_extendable = matches!(inst.opcode,
+                Opcode::ImmToRm | Opcode::AddImmToAcc | Opcode::AdcImmToAcc | Opcode::SubImmFromAcc |
+                Opcode::SbbImmFromAcc | Opcode::CmpImmToAcc | Opcode::AndImmToAcc | Opcode::OrImmToAcc |
+                Opcode::XorImmToAcc);
+            let fits_byte = (-128..=127).contains(&(imm.value as i16));
+            match (sign_extendable && inst.w && !imm.signed && fits_byte, imm.signed) {
+                (true, _) => format!("STRICT WORD {}", imm.value),
+                (false, true) => format!("{}", imm.value as i16),
+                (false, false) => format!("{}", imm.value),
+            }
+        },
+        Operand::Mem(mem_op) => {
+            let seg = match mem_op.segment {
+                Some(seg) => format!("{}:", seg),
+                None => String::new(),
+            };
+
+            let disp = mem_op.disp.unwrap_or(0) as i16;
+            let fits_byte = (-128..=127).contains(&disp);
+
+            // Force the displacement size where NASM would otherwise shrink it
+            let size = match (mem_op.ea, mem_op.disp16, mem_op.disp) {
+                (EffectiveAddress::Direct, _, _) => "",
+                (_, true, _) if fits_byte => "WORD ",
+                (EffectiveAddress::BP, _, _) => "",
+                (_, false, Some(0)) => "BYTE ",
+                _ => "",
+            };
+
+            match (mem_op.ea, mem_op.disp) {
+                (EffectiveAddress::Direct, disp) => format!("[{}{}]", seg, disp.unwrap_or(0)),
+                (ea, None) => format!("[{}{}]", seg, ea),
+                (ea, Some(_)) if disp < 0 => format!("[{}{}{} - {}]", size, seg, ea, disp.unsigned_abs()),
+                (ea, Some(_)) => format!("[{}{}{} + {}]", size, seg, ea, disp),
+            }
+        },
+        _ => operand.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    const LISTINGS: [(&str, &[u8]); 4] = [
+        ("listing_0041_add_sub_cmp_jnz", include_bytes!("../data/listing_0041_add_sub_cmp_jnz")),
+        ("listing_0043_immediate_movs", include_bytes!("../data/listing_0043_immediate_movs")),
+        ("listing_0044_register_movs", include_bytes!("../data/listing_0044_register_movs")),
+        ("listing_0045_challenge_register_movs", include_bytes!("../data/listing_0045_challenge_register_movs")),
+    ];
+
+    fn line(bytes: &[u8]) -> String {
+        let inst = decode(bytes).unwrap();
+        nasm(&inst, &bytes[..inst.len], 0, &BTreeMap::new())
+    }
+
+    #[test]
+    fn canonical_encodings_are_disassembled() {
+        assert_eq!(line(&[0x89, 0xD8]), "MOV AX, BX");
+        assert_eq!(line(&[0x8B, 0x07]), "MOV AX, [BX]");
+        assert_eq!(line(&[0xA1, 0x00, 0x10]), "MOV AX, [4096]");
+        assert_eq!(line(&[0x83, 0xC0, 0x05]), "ADD AX, 5");
+        assert_eq!(line(&[0x81, 0xC3, 0x00, 0x10]), "ADD BX, 4096");
+        assert_eq!(line(&[0x26, 0x8B, 0x07]), "MOV AX, [ES:BX]");
+        // XCHG's operands swap so the first register lands back in REG
+        assert_eq!(line(&[0x87, 0xD9]), "XCHG BX, CX");
+        assert_eq!(line(&[0x86, 0x07]), "XCHG [BX], AL");
+        // REP on anything but a string instruction isn't dropped
+        assert_eq!(line(&[0xF3, 0x90]), "db 0xf3, 0x90 ; XCHG AX, AX");
+        assert_eq!(line(&[0xF3, 0xC3]), "db 0xf3, 0xc3 ; RET");
+        assert_eq!(line(&[0xF3, 0xA4]), "REP MOVSB");
+    }
+
+    #[test]
+    fn redundant_encodings_are_emitted_as_db() {
+        let redundant: [&[u8]; 12] = [
+            &[0x8B, 0xC3],             // MOV AX, BX with D=1
+            &[0x03, 0xC3],             // ADD AX, BX with D=1
+            &[0x8B, 0x06, 0x00, 0x10], // MOV AX, [4096] instead of A1
+            &[0x89, 0x06, 0x00, 0x10], // MOV [4096], AX instead of A3
+            &[0x81, 0xC0, 0x00, 0x10], // ADD AX, 4096 instead of 05
+            &[0x80, 0xC0, 0x05],       // ADD AL, 5 instead of 04
+            &[0x82, 0xC3, 0x05],       // ADD BL, 5 through 82
+            &[0xC7, 0xC0, 0x00, 0x10], // MOV AX, 4096 instead of B8
+            &[0xFF, 0xC0],             // INC AX instead of 40
+            &[0xFF, 0xF0],             // PUSH AX instead of 50
+            &[0x87, 0xC0],             // XCHG AX, AX instead of 90
+            &[0xF3, 0x26, 0xA4],       // REP ES: MOVSB with the prefixes in NASM's opposite order
+        ];
+        for bytes in redundant {
+            assert!(line(bytes).starts_with(&db(bytes)), "{:02X?}: {}", bytes, line(bytes));
+        }
+    }
+
+    #[test]
+    fn linear_disassembly_starts_at_entry() {
+        // Header-like data before the entry would otherwise decode as MOV AX, 4096
+        let (source, errors) = disassemble(&[0xB8, 0x00, 0x10, 0x90, 0xF4], 3);
+        assert!(errors.is_empty());
+        assert_eq!(source, "bits 16\n\ndb 0xb8, 0x00, 0x10\nXCHG AX, AX\nHLT\n");
+    }
+
+    #[test]
+    fn recursive_disassembly_skips_data_between_branches() {
+        // JZ 6; JMP SHORT 7; two data bytes; INC AX; HLT. Decoded linearly, the data swallows INC AX.
+        let program = [0x74, 0x04, 0xEB, 0x03, 0xB8, 0xC3, 0x40, 0xF4];
+        let (source, errors) = disassemble_recursive(&program, 0);
+        assert!(errors.is_empty());
+        assert_eq!(source, "bits 16\n\nJE label_0\nJMP SHORT label_1\ndb 0xb8, 0xc3\nlabel_0:\nINC AX\nlabel_1:\nHLT\n");
+
+        let (source, _) = disassemble(&program, 0);
+        assert!(source.contains("MOV AX, 16579\n"), "{}", source);
+    }
+
+    /// Reassembles every listing with NASM and compares the bytes. Run with `cargo test -- --ignored` where NASM is installed.
+    #[test]
+    #[ignore = "needs nasm"]
+    fn listings_reassemble_byte_for_byte() {
+        let dir = std::env::temp_dir();
+        for (name, bytes) in LISTINGS {
+            let (source, errors) = disassemble(bytes, 0);
+            assert!(errors.is_empty(), "{}: {:?}", name, errors);
+
+            let asm = dir.join(format!("sim86_{}.asm", name));
+            let bin = dir.join(format!("sim86_{}.bin", name));
+            std::fs::write(&asm, source).unwrap();
+            let status = Command::new("nasm").arg("-f").arg("bin").arg("-o").arg(&bin).arg(&asm).status().unwrap();
+            assert!(status.success(), "{}: nasm failed", name);
+            assert_eq!(std::fs::read(&bin).unwrap(), bytes, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use super::mem::*;
+
+/// Handles 0-4 are stdin, stdout, stderr, AUX and PRN; files get the ones after
+const FIRST_FILE_HANDLE: u16 = 5;
+
+/// Handles a program can have open at once, as with FILES=20
+const MAX_HANDLES: u16 = 20;
+
+/// Version reported by function 30h: 5.0
+const VERSION: (u8, u8) = (5, 0);
+
+/// DOS error codes returned in AX with CF set
+const ERR_INVALID_FUNCTION: u16 = 0x01;
+const ERR_FILE_NOT_FOUND: u16 = 0x02;
+const ERR_PATH_NOT_FOUND: u16 = 0x03;
+const ERR_TOO_MANY_FILES: u16 = 0x04;
+const ERR_ACCESS_DENIED: u16 = 0x05;
+const ERR_INVALID_HANDLE: u16 = 0x06;
+const ERR_INVALID_ACCESS: u16 = 0x0C;
+
+/// INT 21h services, with file access confined to one host directory
+#[derive(Debug)]
+pub struct Dos {
+    root: PathBuf, // Host directory DOS paths resolve inside
+    files: BTreeMap<u16, File>,
+    pub exit_code: Option<u8>, // Set once the program terminates
+}
+
+impl Dos {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Dos {
+            root: root.into(),
+            files: BTreeMap::new(),
+            exit_code: None,
+        }
+    }
+
+    /// Handles INT 21h with the function in AH
+    pub fn int21(&mut self, mem: &mut Memory) {
+        let al = mem.read_reg(Reg::AL) as u8;
+        let dl = mem.read_reg(Reg::DL) as u8;
+        let bx = mem.read_reg(Reg::BX);
+        let cx = mem.read_reg(Reg::CX);
+        let dx = mem.read_reg(Reg::DX);
+        let ds = mem.read_seg(SegReg::DS);
+
+        match mem.read_reg(Reg::AH) {
+            // Terminate, as INT 20h does
+            0x00 => self.exit_code = Some(0),
+            // Read character with echo
+            0x01 => {
+                let ch = read_console().unwrap_or(0x1A);
+                write_console(&[ch]);
+                mem.write_reg(Reg::AL, u16::from(ch));
+            },
+            // Write character
+            0x02 => {
+                write_console(&[dl]);
+                mem.write_reg(Reg::AL, u16::from(dl));
+            },
+            // Direct console I/O: DL=FFh reads without waiting, setting ZF when nothing is there
+            0x06 => match dl {
+                0xFF => {
+                    let ch = read_console();
+                    mem.set_flag(Flag::ZF, ch.is_none());
+                    mem.write_reg(Reg::AL, u16::from(ch.unwrap_or(0)));
+                },
+                _ => {
+                    write_console(&[dl]);
+                    mem.write_reg(Reg::AL, u16::from(dl));
+                },
+            },
+            // Write '$'-terminated string at DS:DX
+            0x09 => {
+                let text = read_until(mem, ds, dx, b'$');
+                write_console(&text);
+                mem.write_reg(Reg::AL, u16::from(b'$'));
+            },
+            // Set interrupt vector AL to DS:DX
+            0x25 => {
+                let entry = u32::from(al) * 4;
+                mem.store(entry, &dx.to_le_bytes());
+                mem.store(entry + 2, &ds.to_le_bytes());
+            },
+            // Get DOS version
+            0x30 => {
+                mem.write_reg(Reg::AL, u16::from(VERSION.0));
+                mem.write_reg(Reg::AH, u16::from(VERSION.1));
+                mem.write_reg(Reg::BX, 0);
+                mem.write_reg(Reg::CX, 0);
+            },
+            // Get interrupt vector AL into ES:BX
+            0x35 => {
+                let entry = u32::from(al) * 4;
+                let word = |addr: u32| u16::from_le_bytes([mem.read_byte(addr), mem.read_byte(addr + 1)]);
+                let (offset, segment) = (word(entry), word(entry + 2));
+                mem.write_reg(Reg::BX, offset);
+                mem.write_seg(SegReg::ES, segment);
+            },
+            // Create or truncate file named at DS:DX
+            0x3C => {
+                let result = self.path(mem, ds, dx)
+                    .and_then(|path| File::create(path).map_err(error_code))
+                    .and_then(|file| self.add_file(file));
+                finish(mem, result);
+            },
+            // Open file named at DS:DX with access mode AL: 0 read, 1 write, 2 both
+            0x3D => {
+                let mode = al & 0b11;
+                let result = match mode {
+                    3 => Err(ERR_INVALID_ACCESS),
+                    _ => self.path(mem, ds, dx)
+                        .and_then(|path| OpenOptions::new().read(mode != 1).write(mode != 0).open(path).map_err(error_code))
+                        .and_then(|file| self.add_file(file)),
+                };
+                finish(mem, result);
+            },
+            // Close handle BX
+            0x3E => {
+                let result = match self.files.remove(&bx) {
+                    Some(_) => Ok(0),
+                    None => Err(ERR_INVALID_HANDLE),
+                };
+                finish(mem, result);
+            },
+            // Read CX bytes from handle BX into DS:DX
+            0x3F => {
+                let mut buffer = vec![0; usize::from(cx)];
+                let result = match (bx, self.files.get_mut(&bx)) {
+                    (_, Some(file)) => file.read(&mut buffer).map_err(error_code),
+                    (0, None) => io::stdin().read(&mut buffer).map_err(error_code),
+                    _ => Err(ERR_INVALID_HANDLE),
+                };
+                if let Ok(count) = result {
+                    write_bytes(mem, ds, dx, &buffer[..count]);
+                }
+                finish(mem, result.map(|count| count as u16));
+            },
+            // Write CX bytes from DS:DX to handle BX. Writing 0 bytes truncates a file at its position.
+            0x40 => {
+                let buffer = read_bytes(mem, ds, dx, cx);
+                let result = match (bx, self.files.get_mut(&bx)) {
+                    (_, Some(file)) if cx == 0 => file.stream_position()
+                        .and_then(|pos| file.set_len(pos))
+                        .map(|_| 0)
+                        .map_err(error_code),
+                    (_, Some(file)) => file.write(&buffer).map_err(error_code),
+                    (1, None) => {
+                        write_console(&buffer);
+                        Ok(buffer.len())
+                    },
+                    (2, None) => io::stderr().write(&buffer).map_err(error_code),
+                    _ => Err(ERR_INVALID_HANDLE),
+                };
+                finish(mem, result.map(|count| count as u16));
+            },
+            // Delete file named at DS:DX
+            0x41 => {
+                let result = self.path(mem, ds, dx)
+                    .and_then(|path| fs::remove_file(path).map_err(error_code))
+                    .map(|_| 0);
+                finish(mem, result);
+            },
+            // Move handle BX by CX:DX from the start (AL=0), current position (1) or end (2)
+            0x42 => {
+                let offset = u32::from(cx) << 16 | u32::from(dx);
+                let from = match al {
+                    0 => Some(SeekFrom::Start(u64::from(offset))),
+                    1 => Some(SeekFrom::Current(i64::from(offset as i32))),
+                    2 => Some(SeekFrom::End(i64::from(offset as i32))),
+                    _ => None,
+                };
+                let result = match (self.files.get_mut(&bx), from) {
+                    (None, _) => Err(ERR_INVALID_HANDLE),
+                    (_, None) => Err(ERR_INVALID_FUNCTION),
+                    (Some(file), Some(from)) => file.seek(from).map_err(error_code),
+                };
+                if let Ok(pos) = result {
+                    mem.write_reg(Reg::DX, (pos >> 16) as u16);
+                }
+                finish(mem, result.map(|pos| pos as u16));
+            },
+            // Terminate with exit code AL
+            0x4C => self.exit_code = Some(al),
+            _ => finish(mem, Err(ERR_INVALID_FUNCTION)),
+        }
+    }
+
+    /// Host path for the ASCIIZ DOS path at segment:offset. Paths that could leave the root are refused.
+    fn path(&self, mem: &Memory, segment: u16, offset: u16) -> Result<PathBuf, u16> {
+        let name = String::from_utf8_lossy(&read_until(mem, segment, offset, 0)).replace('\\', "/");
+        let relative = Path::new(&name);
+        if name.contains(':') || !relative.components().all(|part| matches!(part, Component::Normal(_) | Component::CurDir)) {
+            return Err(ERR_PATH_NOT_FOUND);
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// Gives an opened file the lowest free handle
+    fn add_file(&mut self, file: File) -> Result<u16, u16> {
+        let handle = (FIRST_FILE_HANDLE..MAX_HANDLES)
+            .find(|handle| !self.files.contains_key(handle))
+            .ok_or(ERR_TOO_MANY_FILES)?;
+        self.files.insert(handle, file);
+        Ok(handle)
+    }
+}
+
+/// Returns AX and clears CF on success, or sets CF with the error code in AX
+fn finish(mem: &mut Memory, result: Result<u16, u16>) {
+    let (ax, failed) = match result {
+        Ok(ax) => (ax, false),
+        Err(code) => (code, true),
+    };
+    mem.write_reg(Reg::AX, ax);
+    mem.set_flag(Flag::CF, failed);
+}
+
+fn error_code(err: io::Error) -> u16 {
+    match err.kind() {
+        io::ErrorKind::NotFound => ERR_FILE_NOT_FOUND,
+        _ => ERR_ACCESS_DENIED,
+    }
+}
+
+fn read_console() -> Option<u8> {
+    let mut ch = [0];
+    match io::stdin().read(&mut ch) {
+        Ok(1) => Some(ch[0]),
+        _ => None,
+    }
+}
+
+fn write_console(bytes: &[u8]) {
+    let mut stdout = io::stdout();
+    // Console output is best effort; a closed stdout shouldn't stop the program
+    let _ = stdout.write_all(bytes).and_then(|_| stdout.flush());
+}
+
+/// Bytes from segment:offset up to, but not including, `terminator`
+fn read_until(mem: &Memory, segment: u16, offset: u16, terminator: u8) -> Vec<u8> {
+    (0..=u16::MAX)
+        .map(|idx| mem.read_byte(physical_address(segment, offset.wrapping_add(idx))))
+        .take_while(|byte| *byte != terminator)
+        .collect()
+}
+
+fn read_bytes(mem: &Memory, segment: u16, offset: u16, count: u16) -> Vec<u8> {
+    mem.fetch(segment, offset, count)
+}
+
+fn write_bytes(mem: &mut Memory, segment: u16, offset: u16, bytes: &[u8]) {
+    for (idx, byte) in bytes.iter().enumerate() {
+        mem.store(physical_address(segment, offset.wrapping_add(idx as u16)), &[*byte]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAME: u16 = 0x200; // DS:DX for file names
+    const BUFFER: u16 = 0x300;
+
+    /// Empty directory of its own for each test
+    fn sandbox(test: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("sim86_dos_{}_{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    /// Calls function `ah` with the other registers as given, returning AX or the error code
+    fn call(dos: &mut Dos, mem: &mut Memory, ah: u8, regs: &[(Reg, u16)]) -> Result<u16, u16> {
+        for (reg, val) in regs {
+            mem.write_reg(*reg, *val);
+        }
+        mem.write_reg(Reg::AH, u16::from(ah));
+        dos.int21(mem);
+        let ax = mem.read_reg(Reg::AX);
+        if mem.get_flag(Flag::CF) { Err(ax) } else { Ok(ax) }
+    }
+
+    fn open(dos: &mut Dos, mem: &mut Memory, name: &str, mode: u16) -> Result<u16, u16> {
+        mem.store(u32::from(NAME), format!("{}\0", name).as_bytes());
+        call(dos, mem, 0x3D, &[(Reg::AL, mode), (Reg::DX, NAME)])
+    }
+
+    #[test]
+    fn paths_cannot_leave_the_sandbox() {
+        let root = sandbox("escape");
+        fs::write(root.join("inside.txt"), "").unwrap();
+        let (mut dos, mut mem) = (Dos::new(root.join("sub")), Memory::new());
+        fs::create_dir(root.join("sub")).unwrap();
+
+        let refused = ["..\\inside.txt", "../inside.txt", "sub/../../inside.txt", "./..", "\\etc\\passwd", "/etc/passwd", "C:inside.txt", "C:\\inside.txt", "a:b"];
+        for name in refused {
+            assert_eq!(open(&mut dos, &mut mem, name, 0), Err(ERR_PATH_NOT_FOUND), "{}", name);
+        }
+        assert_eq!(open(&mut dos, &mut mem, "missing.txt", 0), Err(ERR_FILE_NOT_FOUND));
+
+        fs::write(root.join("sub/ok.txt"), "").unwrap();
+        assert_eq!(open(&mut dos, &mut mem, ".\\ok.txt", 0), Ok(FIRST_FILE_HANDLE));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn files_round_trip_through_handles() {
+        let root = sandbox("files");
+        let (mut dos, mut mem) = (Dos::new(&root), Memory::new());
+
+        mem.store(u32::from(NAME), b"OUT.TXT\0");
+        let handle = call(&mut dos, &mut mem, 0x3C, &[(Reg::DX, NAME)]).unwrap();
+        assert_eq!(handle, FIRST_FILE_HANDLE);
+        mem.store(u32::from(BUFFER), b"hello, world");
+        assert_eq!(call(&mut dos, &mut mem, 0x40, &[(Reg::BX, handle), (Reg::CX, 12), (Reg::DX, BUFFER)]), Ok(12));
+        assert_eq!(call(&mut dos, &mut mem, 0x3E, &[(Reg::BX, handle)]), Ok(0));
+        assert_eq!(call(&mut dos, &mut mem, 0x3E, &[(Reg::BX, handle)]), Err(ERR_INVALID_HANDLE));
+        assert_eq!(fs::read(root.join("OUT.TXT")).unwrap(), b"hello, world");
+
+        // Read back from offset 7, with the position returned in DX:AX
+        let handle = open(&mut dos, &mut mem, "OUT.TXT", 0).unwrap();
+        assert_eq!(call(&mut dos, &mut mem, 0x42, &[(Reg::AL, 0), (Reg::BX, handle), (Reg::CX, 0), (Reg::DX, 7)]), Ok(7));
+        assert_eq!(mem.read_reg(Reg::DX), 0);
+        assert_eq!(call(&mut dos, &mut mem, 0x3F, &[(Reg::BX, handle), (Reg::CX, 100), (Reg::DX, BUFFER)]), Ok(5));
+        assert_eq!(mem.fetch(0, BUFFER, 5), b"world");
+        assert_eq!(call(&mut dos, &mut mem, 0x3F, &[(Reg::BX, handle), (Reg::CX, 100), (Reg::DX, BUFFER)]), Ok(0));
+        // Opened for reading only
+        assert_eq!(call(&mut dos, &mut mem, 0x40, &[(Reg::BX, handle), (Reg::CX, 1), (Reg::DX, BUFFER)]), Err(ERR_ACCESS_DENIED));
+        assert_eq!(call(&mut dos, &mut mem, 0x3E, &[(Reg::BX, handle)]), Ok(0));
+
+        // Read-write, truncated at the position by a 0-byte write
+        let handle = open(&mut dos, &mut mem, "OUT.TXT", 2).unwrap();
+        assert_eq!(call(&mut dos, &mut mem, 0x42, &[(Reg::AL, 0), (Reg::BX, handle), (Reg::CX, 0), (Reg::DX, 5)]), Ok(5));
+        assert_eq!(call(&mut dos, &mut mem, 0x40, &[(Reg::BX, handle), (Reg::CX, 0), (Reg::DX, BUFFER)]), Ok(0));
+        assert_eq!(call(&mut dos, &mut mem, 0x3E, &[(Reg::BX, handle)]), Ok(0));
+        assert_eq!(fs::read(root.join("OUT.TXT")).unwrap(), b"hello");
+
+        assert_eq!(open(&mut dos, &mut mem, "OUT.TXT", 3), Err(ERR_INVALID_ACCESS));
+        mem.store(u32::from(NAME), b"OUT.TXT\0");
+        assert_eq!(call(&mut dos, &mut mem, 0x41, &[(Reg::DX, NAME)]), Ok(0));
+        assert_eq!(open(&mut dos, &mut mem, "OUT.TXT", 0), Err(ERR_FILE_NOT_FOUND));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn handles_run_out_after_the_file_limit() {
+        let root = sandbox("handles");
+        fs::write(root.join("A"), "").unwrap();
+        let (mut dos, mut mem) = (Dos::new(&root), Memory::new());
+
+        for handle in FIRST_FILE_HANDLE..MAX_HANDLES {
+            assert_eq!(open(&mut dos, &mut mem, "A", 0), Ok(handle));
+        }
+        assert_eq!(open(&mut dos, &mut mem, "A", 0), Err(ERR_TOO_MANY_FILES));
+        // The lowest free handle is reused
+        assert_eq!(call(&mut dos, &mut mem, 0x3E, &[(Reg::BX, 7)]), Ok(0));
+        assert_eq!(open(&mut dos, &mut mem, "A", 0), Ok(7));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn terminate_functions_set_the_exit_code() {
+        let (mut dos, mut mem) = (Dos::new("."), Memory::new());
+        mem.write_reg(Reg::AL, 3);
+        assert!(call(&mut dos, &mut mem, 0x00, &[]).is_ok());
+        assert_eq!(dos.exit_code, Some(0));
+
+        let mut dos = Dos::new(".");
+        call(&mut dos, &mut mem, 0x4C, &[(Reg::AL, 3)]).unwrap();
+        assert_eq!(dos.exit_code, Some(3));
+
+        let mut dos = Dos::new(".");
+        assert_eq!(call(&mut dos, &mut mem, 0xFF, &[]), Err(ERR_INVALID_FUNCTION));
+        assert_eq!(dos.exit_code, None);
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt::{self, Formatter};
+
+/// Bytes in an MZ page, the unit the header measures the file in
+const PAGE_SIZE: usize = 512;
+
+/// Bytes in a paragraph, the unit of the header size and segment values
+const PARAGRAPH_SIZE: usize = 16;
+
+/// Fixed part of the MZ header, before the relocation table
+const HEADER_LEN: usize = 0x1C;
+
+/// Why a file couldn't be loaded as an MZ executable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExeError {
+    BadSignature, // Doesn't start with "MZ"
+    Truncated, // File ends before the header, relocation table or load module does
+    TooLarge, // Load module and minimum allocation don't fit below the top of conventional memory
+}
+
+impl fmt::Display for ExeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::BadSignature => write!(f, "missing MZ signature"),
+            Self::Truncated => write!(f, "truncated MZ executable"),
+            Self::TooLarge => write!(f, "MZ executable needs more memory than is available"),
+        }
+    }
+}
+
+impl std::error::Error for ExeError {}
+
+/// A parsed MZ executable. Segment values are relative to the start of the load module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exe<'a> {
+    pub module: &'a [u8], // Load module: the file minus its header
+    pub relocations: Vec<(u16, u16)>, // Segment:offset of each word holding a segment value
+    pub cs: u16,
+    pub ip: u16,
+    pub ss: u16,
+    pub sp: u16,
+    pub min_alloc: u16, // Paragraphs needed past the load module
+}
+
+impl<'a> Exe<'a> {
+    pub fn parse(file: &'a [u8]) -> Result<Self, ExeError> {
+        if file.len() < HEADER_LEN {
+            return Err(ExeError::Truncated);
+        }
+        if !file.starts_with(b"MZ") && !file.starts_with(b"ZM") {
+            return Err(ExeError::BadSignature);
+        }
+        let word = |offset: usize| u16::from_le_bytes([file[offset], file[offset + 1]]);
+
+        // The last page only holds `last_page_len` bytes, unless that's 0
+        let last_page_len = usize::from(word(0x02));
+        let pages = usize::from(word(0x04));
+        let file_len = match last_page_len {
+            0 => pages * PAGE_SIZE,
+            _ => pages.saturating_sub(1) * PAGE_SIZE + last_page_len,
+        };
+        let header_len = usize::from(word(0x08)) * PARAGRAPH_SIZE;
+        // Some linkers overstate the size, so trust whatever the file actually holds
+        let module_end = file_len.min(file.len());
+        if header_len > module_end {
+            return Err(ExeError::Truncated);
+        }
+
+        let reloc_count = usize::from(word(0x06));
+        let reloc_start = usize::from(word(0x18));
+        if reloc_start + reloc_count * 4 > file.len() {
+            return Err(ExeError::Truncated);
+        }
+        let relocations = (0..reloc_count)
+            .map(|idx| reloc_start + idx * 4)
+            .map(|entry| (word(entry + 2), word(entry)))
+            .collect();
+
+        Ok(Exe {
+            module: &file[header_len..module_end],
+            relocations,
+            cs: word(0x16),
+            ip: word(0x14),
+            ss: word(0x0E),
+            sp: word(0x10),
+            min_alloc: word(0x0A),
+        })
+    }
+
+    /// Offset of the entry point CS:IP within the load module
+    pub fn entry(&self) -> usize {
+        usize::from(self.cs) * PARAGRAPH_SIZE + usize::from(self.ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Cpu, PSP_SEGMENT};
+    use crate::mem::*;
+
+    /// MZ file around `module`, entering at 0001:0004 with the stack at 0002:0080
+    fn image(module: &[u8], relocations: &[(u16, u16)], min_alloc: u16) -> Vec<u8> {
+        let header_len = (HEADER_LEN + relocations.len() * 4).div_ceil(PARAGRAPH_SIZE) * PARAGRAPH_SIZE;
+        let file_len = header_len + module.len();
+        let fields = [
+            u16::from_le_bytes(*b"MZ"),
+            (file_len % PAGE_SIZE) as u16,
+            file_len.div_ceil(PAGE_SIZE) as u16,
+            relocations.len() as u16,
+            (header_len / PARAGRAPH_SIZE) as u16,
+            min_alloc,
+            0xFFFF, // Maximum allocation
+            0x0002, // SS
+            0x0080, // SP
+            0, // Checksum
+            0x0004, // IP
+            0x0001, // CS
+            HEADER_LEN as u16,
+            0, // Overlay
+        ];
+        let mut file: Vec<u8> = fields.iter().flat_map(|field| field.to_le_bytes()).collect();
+        for (segment, offset) in relocations {
+            file.extend(offset.to_le_bytes());
+            file.extend(segment.to_le_bytes());
+        }
+        file.resize(header_len, 0);
+        file.extend(module);
+        file
+    }
+
+    #[test]
+    fn parse_reads_the_header() {
+        let module: Vec<u8> = (0..0x30).collect();
+        let file = image(&module, &[(1, 6)], 0x10);
+        let exe = Exe::parse(&file).unwrap();
+        assert_eq!(exe.module, &module[..]);
+        assert_eq!(exe.relocations, [(1, 6)]);
+        assert_eq!((exe.cs, exe.ip, exe.ss, exe.sp, exe.min_alloc), (1, 4, 2, 0x80, 0x10));
+        assert_eq!(exe.entry(), 0x14);
+
+        // Bytes past the length in the header, such as overlays, aren't part of the module
+        let mut overlaid = file.clone();
+        overlaid.extend([0xAA; 8]);
+        assert_eq!(Exe::parse(&overlaid).unwrap().module, &module[..]);
+
+        // A last page length of 0 means the last page is full
+        let module = vec![0x90; PAGE_SIZE - 0x20];
+        let file = image(&module, &[], 0);
+        assert_eq!(file[0x02..0x04], [0, 0]);
+        assert_eq!(Exe::parse(&file).unwrap().module.len(), module.len());
+    }
+
+    #[test]
+    fn parse_rejects_bad_headers() {
+        let file = image(&[0x90; 0x10], &[(0, 0)], 0);
+        assert_eq!(Exe::parse(&file[..HEADER_LEN - 1]), Err(ExeError::Truncated));
+
+        let mut swapped = file.clone();
+        swapped[..2].copy_from_slice(b"ZM");
+        assert!(Exe::parse(&swapped).is_ok());
+        let mut unsigned = file.clone();
+        unsigned[..2].copy_from_slice(b"MX");
+        assert_eq!(Exe::parse(&unsigned), Err(ExeError::BadSignature));
+
+        // Header bigger than the file
+        let mut header = file.clone();
+        header[0x08] = 0x10;
+        assert_eq!(Exe::parse(&header), Err(ExeError::Truncated));
+
+        // Relocation table past the end
+        let mut relocations = file.clone();
+        relocations[0x06] = 0x20;
+        assert_eq!(Exe::parse(&relocations), Err(ExeError::Truncated));
+    }
+
+    #[test]
+    fn load_relocates_segments_after_the_psp() {
+        // The word at 0001:0006 holds segment 0002, relative to the load module
+        let mut module = vec![0x90; 0x30];
+        module[0x16..0x18].copy_from_slice(&[0x02, 0x00]);
+        let file = image(&module, &[(1, 6)], 0);
+        let mut cpu = Cpu::new();
+        cpu.load_exe(&Exe::parse(&file).unwrap(), "A B").unwrap();
+
+        let load_segment = PSP_SEGMENT + 0x10;
+        let relocated = physical_address(load_segment + 1, 6);
+        assert_eq!([cpu.mem.read_byte(relocated), cpu.mem.read_byte(relocated + 1)], (load_segment + 2).to_le_bytes());
+        assert_eq!(cpu.mem.read_byte(physical_address(load_segment, 0x15)), 0x90);
+
+        let mem = &cpu.mem;
+        assert_eq!((mem.read_seg(SegReg::CS), mem.read_ip()), (load_segment + 1, 4));
+        assert_eq!((mem.read_seg(SegReg::SS), mem.read_reg(Reg::SP)), (load_segment + 2, 0x80));
+        assert_eq!((mem.read_seg(SegReg::DS), mem.read_seg(SegReg::ES)), (PSP_SEGMENT, PSP_SEGMENT));
+        assert_eq!(mem.fetch(PSP_SEGMENT, 0x80, 5), b"\x03A B\r");
+    }
+
+    #[test]
+    fn load_refuses_modules_that_dont_fit() {
+        let file = image(&[0x90; 0x30], &[], 0x9000);
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.load_exe(&Exe::parse(&file).unwrap(), ""), Err(ExeError::TooLarge));
+        assert_eq!(cpu.mem.fetch(PSP_SEGMENT, 0, 2), [0, 0]);
+
+        // Up to the top of conventional memory at A000:0000
+        let file = image(&[0x90; 0x30], &[], 0xA000 - PSP_SEGMENT - 0x10 - 3);
+        assert!(cpu.load_exe(&Exe::parse(&file).unwrap(), "").is_ok());
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use super::cpu::Cpu;
+use super::mem::*;
+
+/// Instructions run between checks for an interrupt from the debugger while continuing
+const INTERRUPT_CHECK_INTERVAL: u64 = 4096;
+
+/// Largest packet we accept, advertised in qSupported
+const PACKET_SIZE: usize = 0x1000;
+
+/// Byte GDB sends to interrupt a running program
+const INTERRUPT: u8 = 0x03;
+
+/// Registers in GDB's i386 order, which `set architecture i8086` also uses. Each is sent as 32 bits.
+const REGISTERS: [&str; 16] = ["AX", "CX", "DX", "BX", "SP", "BP", "SI", "DI", "IP", "FLAGS", "CS", "SS", "DS", "ES", "FS", "GS"];
+
+/// Stop replies, by signal
+const SIGINT: &str = "S02";
+const SIGILL: &str = "S04";
+const SIGTRAP: &str = "S05";
+
+/// A byte stream GDB is connected over
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// What arrived between `$` and the checksum
+#[derive(Debug, PartialEq, Eq)]
+enum Packet {
+    Valid(String),
+    Corrupt, // Checksum didn't match, so GDB is asked to send it again
+    Oversized, // Longer than PACKET_SIZE; the excess was discarded
+}
+
+/// GDB Remote Serial Protocol stub. Memory and breakpoint addresses are physical;
+/// the program counter GDB sees is IP, so breakpoints are best set as `break *0xADDR`.
+pub struct GdbServer {
+    pub cpu: Cpu,
+    breakpoints: BTreeSet<u32>,
+}
+
+impl GdbServer {
+    pub fn new(cpu: Cpu) -> Self {
+        GdbServer {
+            cpu,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Answers packets until GDB detaches, kills the program or disconnects
+    pub fn serve(&mut self, conn: &mut impl Connection) -> io::Result<()> {
+        while let Some(packet) = read_packet(conn)? {
+            let packet = match packet {
+                Packet::Valid(packet) => packet,
+                Packet::Corrupt => {
+                    conn.write_all(b"-")?;
+                    continue;
+                },
+                // Acting on part of a packet, such as half an `M` write, would be worse than refusing it
+                Packet::Oversized => {
+                    conn.write_all(b"+")?;
+                    send(conn, "E01")?;
+                    continue;
+                },
+            };
+            conn.write_all(b"+")?;
+
+            match packet.as_str() {
+                "D" => return send(conn, "OK"),
+                "k" => return Ok(()),
+                _ => {
+                    let reply = self.handle(&packet, conn)?;
+                    send(conn, &reply)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Reply to one packet. Unsupported packets get an empty reply.
+    fn handle(&mut self, packet: &str, conn: &mut impl Connection) -> io::Result<String> {
+        let (kind, body) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match kind {
+            "?" => self.stop_reply(SIGTRAP),
+            "g" => REGISTERS.iter().map(|name| hex_u32(u32::from(self.read_register(name)))).collect(),
+            "G" => {
+                let values: Option<Vec<u32>> = (0..REGISTERS.len())
+                    .map(|idx| body.get(idx * 8..idx * 8 + 8).and_then(parse_u32))
+                    .collect();
+                match values {
+                    Some(values) => {
+                        for (name, value) in REGISTERS.iter().zip(values) {
+                            self.write_register(name, value as u16);
+                        }
+                        String::from("OK")
+                    },
+                    None => String::from("E01"),
+                }
+            },
+            "p" => match usize::from_str_radix(body, 16).ok().and_then(|idx| REGISTERS.get(idx)) {
+                Some(name) => hex_u32(u32::from(self.read_register(name))),
+                None => String::from("E01"),
+            },
+            "P" => {
+                let register = body.split_once('=').and_then(|(idx, value)| {
+                    let name = REGISTERS.get(usize::from_str_radix(idx, 16).ok()?)?;
+                    Some((*name, parse_u32(value)?))
+                });
+                match register {
+                    Some((name, value)) => {
+                        self.write_register(name, value as u16);
+                        String::from("OK")
+                    },
+                    None => String::from("E01"),
+                }
+            },
+            "m" => match parse_range(body) {
+                Some((addr, len)) => (0..len)
+                    .map(|idx| format!("{:02x}", self.cpu.mem.read_byte(addr.wrapping_add(idx))))
+                    .collect(),
+                None => String::from("E01"),
+            },
+            "M" => {
+                let write = body.split_once(':').and_then(|(range, data)| Some((parse_range(range)?, parse_bytes(data)?)));
+                match write {
+                    Some(((addr, len), data)) if data.len() == len as usize => {
+                        for (idx, byte) in data.iter().enumerate() {
+                            self.cpu.mem.write_byte(addr.wrapping_add(idx as u32), *byte);
+                        }
+                        String::from("OK")
+                    },
+                    _ => String::from("E01"),
+                }
+            },
+            // Resuming at another address isn't supported, so any address is ignored
+            "s" => self.resume(conn, true)?,
+            "c" => self.resume(conn, false)?,
+            // Software and hardware execution breakpoints are the same thing here
+            "Z" | "z" => match body.split(',').collect::<Vec<&str>>()[..] {
+                ["0" | "1", addr, _] => match u32::from_str_radix(addr, 16) {
+                    Ok(addr) => {
+                        match kind {
+                            "Z" => self.breakpoints.insert(addr),
+                            _ => self.breakpoints.remove(&addr),
+                        };
+                        String::from("OK")
+                    },
+                    Err(_) => String::from("E01"),
+                },
+                _ => String::new(),
+            },
+            "H" => String::from("OK"),
+            "q" if body.starts_with("Supported") => format!("PacketSize={:x}", PACKET_SIZE),
+            "q" if body == "Attached" => String::from("1"),
+            "q" if body == "fThreadInfo" => String::from("m1"),
+            "q" if body == "sThreadInfo" => String::from("l"),
+            "q" if body == "C" => String::from("QC1"),
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    /// Steps once, or until a breakpoint, the program ends or GDB interrupts
+    fn resume(&mut self, conn: &mut impl Connection, single: bool) -> io::Result<String> {
+        let mut steps: u64 = 0;
+        loop {
+            if !self.cpu.running() {
+                return Ok(self.stop_reply(SIGTRAP));
+            }
+            if self.cpu.step().is_err() {
+                return Ok(String::from(SIGILL));
+            }
+            if single || self.breakpoints.contains(&self.pc()) || !self.cpu.running() {
+                return Ok(self.stop_reply(SIGTRAP));
+            }
+
+            steps += 1;
+            if steps.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && interrupted(conn)? {
+                return Ok(String::from(SIGINT));
+            }
+        }
+    }
+
+    /// `signal` while the program can run, or its exit status once it can't
+    fn stop_reply(&self, signal: &str) -> String {
+        match (self.cpu.running(), self.cpu.dos.exit_code) {
+            (true, _) => String::from(signal),
+            (false, code) => format!("W{:02x}", code.unwrap_or(0)),
+        }
+    }
+
+    /// Physical address of CS:IP
+    fn pc(&self) -> u32 {
+        physical_address(self.cpu.mem.read_seg(SegReg::CS), self.cpu.mem.read_ip())
+    }
+
+    /// FS and GS read as 0, since the 8086 doesn't have them
+    fn read_register(&self, name: &str) -> u16 {
+        self.cpu.mem.read_loc(name).unwrap_or(0)
+    }
+
+    fn write_register(&mut self, name: &str, value: u16) {
+        let mem = &mut self.cpu.mem;
+        match (name, name.parse::<SegReg>(), name.parse::<Reg>()) {
+            ("IP", _, _) => mem.write_ip(value),
+            ("FLAGS", _, _) => mem.write_flags(value),
+            ("FS" | "GS", _, _) => {},
+            (_, Ok(seg), _) => mem.write_seg(seg, value),
+            (_, _, Ok(reg)) => mem.write_reg(reg, value),
+            _ => {},
+        }
+    }
+}
+
+/// Reads the next `$data#checksum` packet, skipping acknowledgements and interrupts that
+/// arrive after the program already stopped. Returns None once the connection closes.
+fn read_packet(conn: &mut impl Read) -> io::Result<Option<Packet>> {
+    let mut byte = [0];
+    loop {
+        if conn.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] == b'$' {
+            break;
+        }
+    }
+
+    let mut data = Vec::new();
+    let mut sum = 0u8;
+    let mut oversized = false;
+    loop {
+        if conn.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        match byte[0] {
+            b'#' => break,
+            _ if data.len() < PACKET_SIZE => data.push(byte[0]),
+            _ => oversized = true,
+        }
+        sum = sum.wrapping_add(byte[0]);
+    }
+
+    let mut checksum = [0; 2];
+    if let Err(err) = conn.read_exact(&mut checksum) {
+        return match err.kind() {
+            io::ErrorKind::UnexpectedEof => Ok(None),
+            _ => Err(err),
+        };
+    }
+    let packet = match (oversized, parse_bytes(&String::from_utf8_lossy(&checksum))) {
+        (true, _) => Packet::Oversized,
+        (false, Some(checksum)) if checksum == [sum] => Packet::Valid(String::from_utf8_lossy(&data).into_owned()),
+        _ => Packet::Corrupt,
+    };
+    Ok(Some(packet))
+}
+
+fn send(conn: &mut impl Write, data: &str) -> io::Result<()> {
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    write!(conn, "${}#{:02x}", data, checksum)?;
+    conn.flush()
+}
+
+/// Whether GDB sent an interrupt, without waiting for one
+fn interrupted(conn: &mut impl Connection) -> io::Result<bool> {
+    conn.set_nonblocking(true)?;
+    let mut byte = [0];
+    let result = match conn.read(&mut byte) {
+        Ok(count) => Ok(count == 1 && byte[0] == INTERRUPT),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(err) => Err(err),
+    };
+    conn.set_nonblocking(false)?;
+    result
+}
+
+/// Little-endian hex, as GDB expects register values
+fn hex_u32(value: u32) -> String {
+    value.to_le_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_u32(hex: &str) -> Option<u32> {
+    let bytes = parse_bytes(hex)?;
+    let mut value = [0; 4];
+    value.get_mut(..bytes.len())?.copy_from_slice(&bytes);
+    Some(u32::from_le_bytes(value))
+}
+
+fn parse_bytes(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+/// `ADDR,LEN` in hex
+fn parse_range(range: &str) -> Option<(u32, u32)> {
+    let (addr, len) = range.split_once(',')?;
+    Some((u32::from_str_radix(addr, 16).ok()?, u32::from_str_radix(len, 16).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GDB's side of the connection: what it sent, and what the stub replied
+    struct Pipe {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Pipe {
+        fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn framed(data: &str) -> String {
+        let mut framed = Vec::new();
+        send(&mut framed, data).unwrap();
+        String::from_utf8(framed).unwrap()
+    }
+
+    /// Serves `packets` to a stub running `program` as a .COM, returning it and everything it sent
+    fn session(program: &[u8], packets: &[&str]) -> (GdbServer, String) {
+        serve(program, packets.iter().map(|packet| framed(packet)).collect())
+    }
+
+    fn serve(program: &[u8], input: String) -> (GdbServer, String) {
+        let mut cpu = Cpu::new();
+        cpu.load_com(program, "");
+        let mut server = GdbServer::new(cpu);
+        let mut pipe = Pipe { input: io::Cursor::new(input.into_bytes()), output: Vec::new() };
+        server.serve(&mut pipe).unwrap();
+        (server, String::from_utf8(pipe.output).unwrap())
+    }
+
+    /// Replies, without acknowledgements or framing
+    fn replies(output: &str) -> Vec<&str> {
+        output.split('$').skip(1).map(|reply| reply.split('#').next().unwrap()).collect()
+    }
+
+    #[test]
+    fn hex_is_little_endian() {
+        assert_eq!(hex_u32(0x1234), "34120000");
+        assert_eq!(parse_u32("34120000"), Some(0x1234));
+        assert_eq!(parse_u32("3412"), Some(0x1234));
+        assert_eq!(parse_u32("ffffffff"), Some(u32::MAX));
+        assert_eq!(parse_u32("0011223344"), None);
+        assert_eq!(parse_u32("341"), None);
+        assert_eq!(parse_u32("zz"), None);
+        assert_eq!(parse_range("10100,2"), Some((0x10100, 2)));
+        assert_eq!(parse_range("10100"), None);
+    }
+
+    #[test]
+    fn packets_are_checked_against_their_checksums() {
+        let read = |input: &[u8]| read_packet(&mut io::Cursor::new(input)).unwrap();
+        assert_eq!(read(b"+\x03$g#67"), Some(Packet::Valid(String::from("g"))));
+        assert_eq!(read(b"$m0,2#fb"), Some(Packet::Valid(String::from("m0,2"))));
+        assert_eq!(read(b"$g#68"), Some(Packet::Corrupt));
+        assert_eq!(read(b"$g#zz"), Some(Packet::Corrupt));
+        assert_eq!(read(b"$g#6"), None);
+        assert_eq!(read(b"$g"), None);
+        assert_eq!(read(b""), None);
+
+        let long = "0".repeat(PACKET_SIZE + 1);
+        assert_eq!(read(framed(&long).as_bytes()), Some(Packet::Oversized));
+        assert!(matches!(read(framed(&long[1..]).as_bytes()), Some(Packet::Valid(_))));
+    }
+
+    #[test]
+    fn corrupt_packets_are_refused_and_oversized_ones_not_applied() {
+        let long_write = format!("M10100,{:x}:{}", PACKET_SIZE, "aa".repeat(PACKET_SIZE));
+        let input = format!("$m10100,1#00{}{}{}", framed(&long_write), framed("m10100,2"), framed("D"));
+        let (server, output) = serve(&[0xF4], input);
+        let acks: String = output.chars().filter(|ch| *ch == '+' || *ch == '-').collect();
+        assert_eq!(acks, "-+++");
+        assert_eq!(replies(&output), ["E01", "f400", "OK"]);
+        assert_eq!(server.cpu.mem.read_byte(0x10101), 0);
+    }
+
+    #[test]
+    fn registers_read_and_write_in_gdb_order() {
+        let registers: String = (0..REGISTERS.len() as u32).map(|idx| hex_u32(idx * 0x1111)).collect();
+        let (server, output) = session(&[0xF4], &["g", &format!("G{}", registers), "p3", "P0=cdab0000", "p0", "Gzz", "D"]);
+        let replies = replies(&output);
+
+        // AX..DI are 0 after loading except SP, then IP, FLAGS, CS, SS, DS, ES, FS and GS
+        let loaded = ["0000", "0000", "0000", "0000", "feff", "0000", "0000", "0000", "0001", "0000", "0010", "0010", "0010", "0010", "0000", "0000"];
+        let loaded: String = loaded.iter().map(|value| format!("{}0000", value)).collect();
+        assert_eq!(replies[0], loaded);
+        assert_eq!(&replies[1..], ["OK", "33330000", "OK", "cdab0000", "E01", "OK"]);
+
+        let mem = &server.cpu.mem;
+        assert_eq!(mem.read_reg(Reg::AX), 0xABCD);
+        assert_eq!(mem.read_reg(Reg::BX), 0x3333);
+        assert_eq!(mem.read_ip(), 0x8888);
+        assert_eq!(mem.read_seg(SegReg::ES), 0xDDDD);
+        // FS and GS aren't there to write
+        assert_eq!(server.read_register("GS"), 0);
+    }
+
+    #[test]
+    fn memory_reads_and_writes_are_physical() {
+        let (server, output) = session(&[0xF4], &["M10100,2:9090", "m100ff,3", "M10100,2:90", "M10100,1:zz", "mzz", "D"]);
+        assert_eq!(replies(&output), ["OK", "009090", "E01", "E01", "E01", "OK"]);
+        assert_eq!(server.cpu.mem.read_byte(0x10100), 0x90);
+    }
+
+    #[test]
+    fn continuing_stops_at_breakpoints_until_removed() {
+        // NOP; NOP; NOP; HLT
+        let program = [0x90, 0x90, 0x90, 0xF4];
+        let (server, output) = session(&program, &["Z0,10102,1", "c", "s", "z0,10102,1", "Z1,10102,1", "c", "Z2,10102,1", "D"]);
+        assert_eq!(replies(&output), ["OK", "S05", "S05", "OK", "OK", "W00", "", "OK"]);
+        assert!(!server.cpu.running());
+
+        let (server, output) = session(&program, &["Z0,10102,1", "z0,10102,1", "c", "D"]);
+        assert_eq!(replies(&output), ["OK", "OK", "W00", "OK"]);
+        assert_eq!(server.cpu.mem.read_ip(), 0x104);
+    }
+}
@@ -1,13 +1,20 @@
 use std::fmt;
+use super::alu;
+use super::decode::Fields;
 use super::mem::*;
+use super::operand::*;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum OpType {
     ADD = 0b000,
+    OR  = 0b001,
+    ADC = 0b010,
+    SBB = 0b011,
+    AND = 0b100,
     SUB = 0b101,
+    XOR = 0b110,
     CMP = 0b111,
-    UNIMPL
 }
 
 impl fmt::Display for OpType {
@@ -18,126 +25,294 @@ impl fmt::Display for OpType {
 
 impl From<u8> for OpType {
     fn from(value: u8) -> Self {
-        match value {
+        match value & 0b111 {
             0b000 => OpType::ADD,
+            0b001 => OpType::OR,
+            0b010 => OpType::ADC,
+            0b011 => OpType::SBB,
+            0b100 => OpType::AND,
             0b101 => OpType::SUB,
-            0b111 => OpType::CMP,
-            _ => OpType::UNIMPL
+            0b110 => OpType::XOR,
+            _ => OpType::CMP,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
-#[repr(u8)]
+// Bit patterns from the 8086 manual instruction encoding table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Opcode {
-    MovRmToReg         = 0b100010,
-    // MovImmToRm      = 0b1100011,
-    MovImmToReg        = 0b1011,
-    // MovMemToAcc     = 0b1010000,
-    // MovAccToMem     = 0b1010001,
-    AddRmAndReg        = 0b000000,
-    AddImmToAcc        = 0b0000010,
-    SubRmAndReg        = 0b001010,
-    SubImmFromAcc      = 0b0010110,
-    CmpRmAndReg        = 0b001110,
-    CmpImmToAcc        = 0b0011110,
-    ImmToRm            = 0b100000,
-    JmpEqual           = 0b01110100,
-    JmpLess            = 0b01111100,
-    JmpLessOrEqual     = 0b01111110,
-    JmpBelow           = 0b01110010,
-    JmpBelowOrEqual    = 0b01110110,
-    JmpParity          = 0b01111010,
-    JmpOverflow        = 0b01110000,
-    JmpSign            = 0b01111000,
-    JmpNotEqual        = 0b01110101,
-    JmpNotLess         = 0b01111101,
-    JmpNotLessOrEqual  = 0b01111111,
-    JmpNotBelow        = 0b01110011,
-    JmpNotBelowOrEqual = 0b01110111,
-    JmpNotParity       = 0b01111011,
-    JmpNotOverflow     = 0b01110001,
-    JmpOnNotSign       = 0b01111001,
-    Loop               = 0b11100010,
-    LoopZero           = 0b11100001,
-    LoopNotZero        = 0b11100000,
-    JmpCXZero          = 0b11100011,
-    Unimpl             = 0b11111111,
+    // Data transfer
+    MovRmToReg,         // 100010 D W
+    MovImmToRm,         // 1100011 W | MOD 000 R/M
+    MovImmToReg,        // 1011 W REG
+    MovMemToAcc,        // 1010000 W
+    MovAccToMem,        // 1010001 W
+    MovRmToSeg,         // 10001110
+    MovSegToRm,         // 10001100
+    PushRm,             // 11111111 | MOD 110 R/M
+    PushReg,            // 01010 REG
+    PushSeg,            // 000 SR 110
+    PopRm,              // 10001111 | MOD 000 R/M
+    PopReg,             // 01011 REG
+    PopSeg,             // 000 SR 111
+    XchgRmWithReg,      // 1000011 W
+    XchgRegWithAcc,     // 10010 REG
+    InFixed,            // 1110010 W
+    InVariable,         // 1110110 W
+    OutFixed,           // 1110011 W
+    OutVariable,        // 1110111 W
+    Xlat,               // 11010111
+    Lea,                // 10001101
+    Lds,                // 11000101
+    Les,                // 11000100
+    Lahf,               // 10011111
+    Sahf,               // 10011110
+    Pushf,              // 10011100
+    Popf,               // 10011101
+
+    // Arithmetic
+    AddRmAndReg,        // 000000 D W
+    AddImmToAcc,        // 0000010 W
+    AdcRmAndReg,        // 000100 D W
+    AdcImmToAcc,        // 0001010 W
+    IncRm,              // 1111111 W | MOD 000 R/M
+    IncReg,             // 01000 REG
+    Aaa,                // 00110111
+    Daa,                // 00100111
+    SubRmAndReg,        // 001010 D W
+    SubImmFromAcc,      // 0010110 W
+    SbbRmAndReg,        // 000110 D W
+    SbbImmFromAcc,      // 0001110 W
+    DecRm,              // 1111111 W | MOD 001 R/M
+    DecReg,             // 01001 REG
+    Neg,                // 1111011 W | MOD 011 R/M
+    CmpRmAndReg,        // 001110 D W
+    CmpImmToAcc,        // 0011110 W
+    Aas,                // 00111111
+    Das,                // 00101111
+    Mul,                // 1111011 W | MOD 100 R/M
+    Imul,               // 1111011 W | MOD 101 R/M
+    Aam,                // 11010100 | 00001010
+    Div,                // 1111011 W | MOD 110 R/M
+    Idiv,               // 1111011 W | MOD 111 R/M
+    Aad,                // 11010101 | 00001010
+    Cbw,                // 10011000
+    Cwd,                // 10011001
+    ImmToRm,            // 100000 S W | MOD OP R/M (ADD/OR/ADC/SBB/AND/SUB/XOR/CMP)
+
+    // Logic
+    Not,                // 1111011 W | MOD 010 R/M
+    Shl,                // 110100 V W | MOD 100 R/M
+    Shr,                // 110100 V W | MOD 101 R/M
+    Sar,                // 110100 V W | MOD 111 R/M
+    Rol,                // 110100 V W | MOD 000 R/M
+    Ror,                // 110100 V W | MOD 001 R/M
+    Rcl,                // 110100 V W | MOD 010 R/M
+    Rcr,                // 110100 V W | MOD 011 R/M
+    AndRmAndReg,        // 001000 D W
+    AndImmToAcc,        // 0010010 W
+    TestRmAndReg,       // 1000010 W
+    TestImmToRm,        // 1111011 W | MOD 000 R/M
+    TestImmToAcc,       // 1010100 W
+    OrRmAndReg,         // 000010 D W
+    OrImmToAcc,         // 0000110 W
+    XorRmAndReg,        // 001100 D W
+    XorImmToAcc,        // 0011010 W
+
+    // String manipulation
+    Movs,               // 1010010 W
+    Cmps,               // 1010011 W
+    Scas,               // 1010111 W
+    Lods,               // 1010110 W
+    Stos,               // 1010101 W
+
+    // Control transfer
+    CallDirect,         // 11101000
+    CallIndirect,       // 11111111 | MOD 010 R/M
+    CallDirectFar,      // 10011010
+    CallIndirectFar,    // 11111111 | MOD 011 R/M
+    JmpDirect,          // 11101001
+    JmpShort,           // 11101011
+    JmpIndirect,        // 11111111 | MOD 100 R/M
+    JmpDirectFar,       // 11101010
+    JmpIndirectFar,     // 11111111 | MOD 101 R/M
+    Ret,                // 11000011
+    RetImm,             // 11000010
+    RetFar,             // 11001011
+    RetFarImm,          // 11001010
+    JmpEqual,           // 01110100
+    JmpLess,            // 01111100
+    JmpLessOrEqual,     // 01111110
+    JmpBelow,           // 01110010
+    JmpBelowOrEqual,    // 01110110
+    JmpParity,          // 01111010
+    JmpOverflow,        // 01110000
+    JmpSign,            // 01111000
+    JmpNotEqual,        // 01110101
+    JmpNotLess,         // 01111101
+    JmpNotLessOrEqual,  // 01111111
+    JmpNotBelow,        // 01110011
+    JmpNotBelowOrEqual, // 01110111
+    JmpNotParity,       // 01111011
+    JmpNotOverflow,     // 01110001
+    JmpOnNotSign,       // 01111001
+    Loop,               // 11100010
+    LoopZero,           // 11100001
+    LoopNotZero,        // 11100000
+    JmpCXZero,          // 11100011
+    Int,                // 11001101
+    Int3,               // 11001100
+    Into,               // 11001110
+    Iret,               // 11001111
+
+    // Processor control
+    Clc,                // 11111000
+    Cmc,                // 11110101
+    Stc,                // 11111001
+    Cld,                // 11111100
+    Std,                // 11111101
+    Cli,                // 11111010
+    Sti,                // 11111011
+    Hlt,                // 11110100
+    Wait,               // 10011011
+    Esc,                // 11011 XXX | MOD XXX R/M
+    Lock,               // 11110000
+    Rep,                // 1111001 Z
+    Segment,            // 001 SR 110
+    Unimpl,
 }
 
 impl fmt::Display for Opcode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // ImmToRm handled separately
         match self {
-            Self::MovImmToReg        => write!(f, "{}", "MOV"),
-            Self::MovRmToReg         => write!(f, "{}", "MOV"),
-            Self::AddImmToAcc        => write!(f, "{}", "ADD"),
-            Self::AddRmAndReg        => write!(f, "{}", "ADD"),
-            Self::SubImmFromAcc      => write!(f, "{}", "SUB"),
-            Self::SubRmAndReg        => write!(f, "{}", "SUB"),
-            Self::CmpImmToAcc        => write!(f, "{}", "CMP"),
-            Self::CmpRmAndReg        => write!(f, "{}", "CMP"),
-            Self::JmpEqual           => write!(f, "{}", "JE"),
-            Self::JmpLess            => write!(f, "{}", "JL"),
-            Self::JmpLessOrEqual     => write!(f, "{}", "JLE"),
-            Self::JmpBelow           => write!(f, "{}", "JB"),
-            Self::JmpBelowOrEqual    => write!(f, "{}", "JBE"),
-            Self::JmpParity          => write!(f, "{}", "JP"),
-            Self::JmpOverflow        => write!(f, "{}", "JO"),
-            Self::JmpSign            => write!(f, "{}", "JS"),
-            Self::JmpNotEqual        => write!(f, "{}", "JNZ"),
-            Self::JmpNotLess         => write!(f, "{}", "JNL"),
-            Self::JmpNotLessOrEqual  => write!(f, "{}", "JNLE"),
-            Self::JmpNotBelow        => write!(f, "{}", "JNB"),
-            Self::JmpNotBelowOrEqual => write!(f, "{}", "JNBE"),
-            Self::JmpNotParity       => write!(f, "{}", "JNP"),
-            Self::JmpNotOverflow     => write!(f, "{}", "JNO"),
-            Self::JmpOnNotSign       => write!(f, "{}", "JNS"),
-            Self::Loop               => write!(f, "{}", "LOOP"),
-            Self::LoopZero           => write!(f, "{}", "LOOPZ"),
-            Self::LoopNotZero        => write!(f, "{}", "LOOPNZ"),
-            Self::JmpCXZero          => write!(f, "{}", "JCXZ"),
-            _ => write!(f, "{}", "UNIMPL")
-        }
-    }
-}
-
-impl From<u8> for Opcode {
-    fn from(value: u8) -> Self {
-        match value {
-            0b100010   => Opcode::MovRmToReg,
-            // 0b1100011 => Opcode::MovImmToRm,
-            0b1101     => Opcode::MovImmToReg,
-            // 0b1010000 => Opcode::MovMemToAcc,
-            // 0b1010001 => Opcode::MovAccToMem,
-            0b000000   => Opcode::AddRmAndReg,
-            0b0000010  => Opcode::AddImmToAcc,
-            0b001010   => Opcode::SubRmAndReg,
-            0b0010110  => Opcode::SubImmFromAcc,
-            0b001110   => Opcode::CmpRmAndReg,
-            0b0011110  => Opcode::CmpImmToAcc,
-            0b100000   => Opcode::ImmToRm,
-            0b01110100 => Opcode::JmpEqual,
-            0b01111100 => Opcode::JmpLess,
-            0b01111110 => Opcode::JmpLessOrEqual,
-            0b01110010 => Opcode::JmpBelow,
-            0b01110110 => Opcode::JmpBelowOrEqual,
-            0b01111010 => Opcode::JmpParity,
-            0b01110000 => Opcode::JmpOverflow,
-            0b01111000 => Opcode::JmpSign,
-            0b01110101 => Opcode::JmpNotEqual,
-            0b01111101 => Opcode::JmpNotLess,
-            0b01111111 => Opcode::JmpNotLessOrEqual,
-            0b01110011 => Opcode::JmpNotBelow,
-            0b01110111 => Opcode::JmpNotBelowOrEqual,
-            0b01111011 => Opcode::JmpNotParity,
-            0b01110001 => Opcode::JmpNotOverflow,
-            0b01111001 => Opcode::JmpOnNotSign,
-            0b11100010 => Opcode::Loop,
-            0b11100001 => Opcode::LoopZero,
-            0b11100000 => Opcode::LoopNotZero,
-            0b11100011 => Opcode::JmpCXZero,
-            _ => Opcode::Unimpl
+            Self::MovImmToReg        => write!(f, "MOV"),
+            Self::MovRmToReg         => write!(f, "MOV"),
+            Self::MovImmToRm         => write!(f, "MOV"),
+            Self::MovMemToAcc        => write!(f, "MOV"),
+            Self::MovAccToMem        => write!(f, "MOV"),
+            Self::MovRmToSeg         => write!(f, "MOV"),
+            Self::MovSegToRm         => write!(f, "MOV"),
+            Self::PushRm             => write!(f, "PUSH"),
+            Self::PushReg            => write!(f, "PUSH"),
+            Self::PushSeg            => write!(f, "PUSH"),
+            Self::PopRm              => write!(f, "POP"),
+            Self::PopReg             => write!(f, "POP"),
+            Self::PopSeg             => write!(f, "POP"),
+            Self::XchgRmWithReg      => write!(f, "XCHG"),
+            Self::XchgRegWithAcc     => write!(f, "XCHG"),
+            Self::InFixed            => write!(f, "IN"),
+            Self::InVariable         => write!(f, "IN"),
+            Self::OutFixed           => write!(f, "OUT"),
+            Self::OutVariable        => write!(f, "OUT"),
+            Self::Xlat               => write!(f, "XLAT"),
+            Self::Lea                => write!(f, "LEA"),
+            Self::Lds                => write!(f, "LDS"),
+            Self::Les                => write!(f, "LES"),
+            Self::Lahf               => write!(f, "LAHF"),
+            Self::Sahf               => write!(f, "SAHF"),
+            Self::Pushf              => write!(f, "PUSHF"),
+            Self::Popf               => write!(f, "POPF"),
+            Self::AddImmToAcc        => write!(f, "ADD"),
+            Self::AddRmAndReg        => write!(f, "ADD"),
+            Self::AdcImmToAcc        => write!(f, "ADC"),
+            Self::AdcRmAndReg        => write!(f, "ADC"),
+            Self::IncRm              => write!(f, "INC"),
+            Self::IncReg             => write!(f, "INC"),
+            Self::Aaa                => write!(f, "AAA"),
+            Self::Daa                => write!(f, "DAA"),
+            Self::SubImmFromAcc      => write!(f, "SUB"),
+            Self::SubRmAndReg        => write!(f, "SUB"),
+            Self::SbbImmFromAcc      => write!(f, "SBB"),
+            Self::SbbRmAndReg        => write!(f, "SBB"),
+            Self::DecRm              => write!(f, "DEC"),
+            Self::DecReg             => write!(f, "DEC"),
+            Self::Neg                => write!(f, "NEG"),
+            Self::CmpImmToAcc        => write!(f, "CMP"),
+            Self::CmpRmAndReg        => write!(f, "CMP"),
+            Self::Aas                => write!(f, "AAS"),
+            Self::Das                => write!(f, "DAS"),
+            Self::Mul                => write!(f, "MUL"),
+            Self::Imul               => write!(f, "IMUL"),
+            Self::Aam                => write!(f, "AAM"),
+            Self::Div                => write!(f, "DIV"),
+            Self::Idiv               => write!(f, "IDIV"),
+            Self::Aad                => write!(f, "AAD"),
+            Self::Cbw                => write!(f, "CBW"),
+            Self::Cwd                => write!(f, "CWD"),
+            Self::Not                => write!(f, "NOT"),
+            Self::Shl                => write!(f, "SHL"),
+            Self::Shr                => write!(f, "SHR"),
+            Self::Sar                => write!(f, "SAR"),
+            Self::Rol                => write!(f, "ROL"),
+            Self::Ror                => write!(f, "ROR"),
+            Self::Rcl                => write!(f, "RCL"),
+            Self::Rcr                => write!(f, "RCR"),
+            Self::AndImmToAcc        => write!(f, "AND"),
+            Self::AndRmAndReg        => write!(f, "AND"),
+            Self::TestImmToAcc       => write!(f, "TEST"),
+            Self::TestImmToRm        => write!(f, "TEST"),
+            Self::TestRmAndReg       => write!(f, "TEST"),
+            Self::OrImmToAcc         => write!(f, "OR"),
+            Self::OrRmAndReg         => write!(f, "OR"),
+            Self::XorImmToAcc        => write!(f, "XOR"),
+            Self::XorRmAndReg        => write!(f, "XOR"),
+            Self::Movs               => write!(f, "MOVS"),
+            Self::Cmps               => write!(f, "CMPS"),
+            Self::Scas               => write!(f, "SCAS"),
+            Self::Lods               => write!(f, "LODS"),
+            Self::Stos               => write!(f, "STOS"),
+            Self::CallDirect         => write!(f, "CALL"),
+            Self::CallIndirect       => write!(f, "CALL"),
+            Self::CallDirectFar      => write!(f, "CALL"),
+            Self::CallIndirectFar    => write!(f, "CALL"),
+            Self::JmpDirect          => write!(f, "JMP"),
+            Self::JmpShort           => write!(f, "JMP"),
+            Self::JmpIndirect        => write!(f, "JMP"),
+            Self::JmpDirectFar       => write!(f, "JMP"),
+            Self::JmpIndirectFar     => write!(f, "JMP"),
+            Self::Ret                => write!(f, "RET"),
+            Self::RetImm             => write!(f, "RET"),
+            Self::RetFar             => write!(f, "RETF"),
+            Self::RetFarImm          => write!(f, "RETF"),
+            Self::JmpEqual           => write!(f, "JE"),
+            Self::JmpLess            => write!(f, "JL"),
+            Self::JmpLessOrEqual     => write!(f, "JLE"),
+            Self::JmpBelow           => write!(f, "JB"),
+            Self::JmpBelowOrEqual    => write!(f, "JBE"),
+            Self::JmpParity          => write!(f, "JP"),
+            Self::JmpOverflow        => write!(f, "JO"),
+            Self::JmpSign            => write!(f, "JS"),
+            Self::JmpNotEqual        => write!(f, "JNZ"),
+            Self::JmpNotLess         => write!(f, "JNL"),
+            Self::JmpNotLessOrEqual  => write!(f, "JNLE"),
+            Self::JmpNotBelow        => write!(f, "JNB"),
+            Self::JmpNotBelowOrEqual => write!(f, "JNBE"),
+            Self::JmpNotParity       => write!(f, "JNP"),
+            Self::JmpNotOverflow     => write!(f, "JNO"),
+            Self::JmpOnNotSign       => write!(f, "JNS"),
+            Self::Loop               => write!(f, "LOOP"),
+            Self::LoopZero           => write!(f, "LOOPZ"),
+            Self::LoopNotZero        => write!(f, "LOOPNZ"),
+            Self::JmpCXZero          => write!(f, "JCXZ"),
+            Self::Int                => write!(f, "INT"),
+            Self::Int3               => write!(f, "INT3"),
+            Self::Into               => write!(f, "INTO"),
+            Self::Iret               => write!(f, "IRET"),
+            Self::Clc                => write!(f, "CLC"),
+            Self::Cmc                => write!(f, "CMC"),
+            Self::Stc                => write!(f, "STC"),
+            Self::Cld                => write!(f, "CLD"),
+            Self::Std                => write!(f, "STD"),
+            Self::Cli                => write!(f, "CLI"),
+            Self::Sti                => write!(f, "STI"),
+            Self::Hlt                => write!(f, "HLT"),
+            Self::Wait               => write!(f, "WAIT"),
+            Self::Esc                => write!(f, "ESC"),
+            Self::Lock               => write!(f, "LOCK"),
+            Self::Rep                => write!(f, "REP"),
+            Self::Segment            => write!(f, "SEGMENT"),
+            _ => write!(f, "UNIMPL")
         }
     }
 }
@@ -151,14 +326,15 @@ pub enum Mode {
     Reg = 0b11,
 }
 
+/// MOD field; only the low 2 bits are looked at
 impl From<u8> for Mode {
     fn from(value: u8) -> Self {
-        match value {
+        match value & 0b11 {
             0b00 => Mode::Mem,
             0b01 => Mode::Mem8,
             0b10 => Mode::Mem16,
             0b11 => Mode::Reg,
-            _ => todo!()
+            _ => unreachable!(),
         }
     }
 }
@@ -166,305 +342,673 @@ impl From<u8> for Mode {
 #[derive(Debug)]
 pub struct Instruction {
     pub raw_bin: String,
+    pub len: usize, // Length in bytes, including prefixes
     pub opcode: Opcode,
-    pub d: bool, // Reg is Destination
+    pub d: bool, // Reg is Destination (V for shifts/rotates: count in CL)
     pub w: bool, // Wide (16 bits)
     pub s: Option<bool>, // Combined with W for ImmReg Add/Sub/Cmp
-    pub mode: Option<Mode>,
-    pub reg: Reg,
-    pub r_m: Option<u8>,
-    pub disp_lo: Option<u8>,
-    pub disp_hi: Option<u8>,
-    pub data: Option<u16>,
-    pub dest: String,
-    pub source: String,
-    pub str_val: String
+    pub op_type: Option<OpType>, // Operation of ImmToRm
+    pub segment: Option<SegReg>, // Segment override prefix
+    pub lock: bool, // LOCK prefix
+    pub rep: Option<bool>, // REP prefix, Some(true) for REP/REPE, Some(false) for REPNE
+    pub dest: Option<Operand>,
+    pub source: Option<Operand>,
+    pub mnemonic: String, // Bare mnemonic, e.g. MOVSB
+    pub size: Option<&'static str>, // BYTE, WORD or FAR, when no register operand implies it
+    pub str_val: String // Prefixes, mnemonic and size as printed, e.g. REP MOVSB or LOCK INC WORD
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.dest, self.source) {
+            (None, _) => write!(f, "{}", self.str_val),
+            (Some(dest), None) => write!(f, "{} {}", self.str_val, dest),
+            (Some(dest), Some(source)) => write!(f, "{} {}, {}", self.str_val, dest, source),
+        }
+    }
+}
+
+/// MOVS, CMPS, SCAS, LODS and STOS, the only instructions REP applies to
+pub fn is_string(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::Movs | Opcode::Cmps | Opcode::Scas | Opcode::Lods | Opcode::Stos)
+}
+
+fn size_str(w: bool) -> &'static str {
+    match w {
+        true => "WORD",
+        false => "BYTE",
+    }
 }
 
 impl Instruction {
-    pub fn new(opcode: Opcode, full_inst: &[u8]) -> Instruction {
+    /// Prefixes the instruction is printed with: LOCK and, on string instructions, REP/REPE/REPNE
+    pub fn prefixes(&self) -> Vec<&'static str> {
+        let lock = self.lock.then_some("LOCK");
+        let rep = match (self.rep, self.opcode) {
+            (_, opcode) if !is_string(opcode) => None,
+            (None, _) => None,
+            (Some(true), Opcode::Cmps | Opcode::Scas) => Some("REPE"),
+            (Some(true), _) => Some("REP"),
+            (Some(false), _) => Some("REPNE"),
+        };
+        lock.into_iter().chain(rep).collect()
+    }
+
+    /// Builds an instruction from the fields decoded out of its encoding.
+    /// `full_inst` is every byte of the instruction, including prefixes.
+    pub fn new(opcode: Opcode, fields: &Fields, full_inst: &[u8]) -> Instruction {
         let mut raw_bin = String::new();
         for inst in full_inst {
             raw_bin.push_str(&format!("{:08b}", inst));
         }
-        let first_byte = full_inst[0];
-
-        let (d, w, s, mode, reg, r_m, disp_lo, disp_hi, data, dest, source, str_val) = match opcode {
-            Opcode::MovRmToReg | Opcode::AddRmAndReg | Opcode::SubRmAndReg | Opcode::CmpRmAndReg => {
-                let second_byte = full_inst[1];
-                let mode_bits = (second_byte >> 6) & 0b11;
-
-                let d = ((first_byte >> 1) & 0b1) != 0;
-                let w = (first_byte & 0b1) != 0;
-                let s = None;
-                let mode =  Some(Mode::from(mode_bits));
-                let reg =  Reg::from((second_byte >> 3 & 0b111) << 1 | u8::from(w));
-                let r_m =  Some(second_byte & 0b111);
-                let data = None;
-
-                let (disp_lo, disp_hi, dest, source) = match Mode::from(mode_bits) {
-                    Mode::Mem => {
-                        match r_m.unwrap() {
-                            0b110 => {
-                                let disp_lo = Some(full_inst[2]);
-                                let disp_hi = Some(full_inst[3]);
-                                let rm = EffectiveAddress::from(r_m.unwrap());
-
-                                let (dest, source) = match d {
-                                    false => (rm.to_string(), reg.to_string()),
-                                    true => (reg.to_string(), rm.to_string())
-                                };
-
-                                (disp_lo, disp_hi, dest, source)
-                            },
-                            _ => {
-                                let disp_lo = None;
-                                let disp_hi = None;
-                                let rm = EffectiveAddress::from(r_m.unwrap());
-
-                                let (dest, source) = match d {
-                                    false => (rm.to_string(), reg.to_string()),
-                                    true => (reg.to_string(), rm.to_string())
-                                };
-
-                                (disp_lo, disp_hi, dest, source)
-                            }
-                        }
-                    },
-                    Mode::Mem8 => {
-                        let disp_lo: Option<u8> = Some(full_inst[2]);
-                        let disp_hi: Option<u8> =  None;
-                        let rm = EffectiveAddress::from(r_m.unwrap());
-                        let dest_str = format!("[{} + {}]", rm.to_string(), disp_lo.unwrap());
-
-                        let (dest, source) = match d {
-                            false => (dest_str, reg.to_string()),
-                            true => (reg.to_string(), dest_str)
-                        };
-
-                        (disp_lo, disp_hi, dest, source)
-                    },
-                    Mode::Mem16 => {
-                        let disp_lo = Some(full_inst[2]);
-                        let disp_hi = Some(full_inst[3]);
-                        let full_disp = u16::from(disp_hi.unwrap()) << 8 | u16::from(disp_lo.unwrap());
-                        let rm = EffectiveAddress::from(r_m.unwrap());
-                        let dest_str = format!("[{} + {}]", rm.to_string(), full_disp);
-
-                        let (dest, source) = match d {
-                            false => (dest_str, reg.to_string()),
-                            true => (reg.to_string(), dest_str)
-                        };
-
-                        (disp_lo, disp_hi, dest, source)
-                    },
-                    Mode::Reg => {
-                        let disp_lo = None;
-                        let disp_hi = None;
-                        let rm = Reg::from(r_m.unwrap() << 1 | u8::from(w));
 
-                        let (dest, source) = match d {
-                            false => (rm.to_string(), reg.to_string()),
-                            true => (reg.to_string(), rm.to_string())
-                        };
-
-                        (disp_lo, disp_hi, dest, source)
-                    }
-                };
+        let w = fields.w.unwrap_or(false);
+        let d = fields.d.or(fields.v).unwrap_or(false);
+        let op_type = match opcode {
+            Opcode::ImmToRm => fields.op.map(OpType::from),
+            _ => None,
+        };
 
-                (d, w, s, mode, reg, r_m, disp_lo, disp_hi, data, dest, source, opcode.to_string())
+        let reg_op = fields.reg.map(|reg| Operand::Reg(Reg::from(reg << 1 | u8::from(w))))
+            .or(fields.sr.map(|sr| Operand::Seg(SegReg::from(sr))));
+        let rm_op = match (fields.mode.map(Mode::from), fields.rm) {
+            (Some(Mode::Reg), Some(rm)) => Some(Operand::Reg(Reg::from(rm << 1 | u8::from(w || fields.rm_wide)))),
+            (Some(mode), Some(rm)) => Some(Operand::Mem(mem_operand(mode, rm, fields))),
+            _ => None,
+        };
+        let imm_op = match (fields.data, fields.data8) {
+            (Some(data), _) => match w {
+                true => Some(Operand::Imm(Immediate { value: data, signed: fields.s })),
+                false => Some(Operand::Imm(Immediate { value: data as u8 as i8 as u16, signed: true })),
             },
-            Opcode::MovImmToReg | Opcode::AddImmToAcc | Opcode::SubImmFromAcc | Opcode::CmpImmToAcc => {
-                let d = false;
-
-                let w = match opcode {
-                    Opcode::MovImmToReg => ((first_byte >> 3) & 0b1) != 0,
-                    Opcode::AddImmToAcc | Opcode::SubImmFromAcc | Opcode::CmpImmToAcc => (first_byte & 0b1) != 0,
-                    _ => false
-                };
+            // Ports and interrupt types are unsigned
+            (None, Some(data8)) => Some(Operand::Imm(Immediate { value: u16::from(data8), signed: false })),
+            (None, None) => None,
+        };
 
-                let s = None;
-                let mode = None;
-                let r_m = None;
-                let disp_lo = None;
-                let disp_hi = None;
+        let (dest, source) = match (reg_op, rm_op, imm_op) {
+            (Some(reg), Some(other), _) | (Some(reg), None, Some(other)) => match d {
+                false => (Some(other), Some(reg)),
+                true => (Some(reg), Some(other)),
+            },
+            (Some(reg), None, None) => (Some(reg), None),
+            (None, Some(rm), Some(imm)) => (Some(rm), Some(imm)),
+            (None, Some(rm), None) => match (opcode, fields.v) {
+                (Opcode::Esc, _) => {
+                    let ext = fields.op.map(u16::from).unwrap_or(0);
+                    (Some(Operand::Imm(Immediate { value: ext, signed: false })), Some(rm))
+                },
+                (_, Some(true)) => (Some(rm), Some(Operand::Reg(Reg::CL))),
+                (_, Some(false)) => (Some(rm), Some(Operand::Imm(Immediate { value: 1, signed: false }))),
+                (_, None) => (Some(rm), None),
+            },
+            (None, None, Some(imm)) => (Some(imm), None),
+            (None, None, None) => match (fields.inc, fields.far) {
+                (Some(inc), _) => (Some(Operand::Rel((inc as i16).wrapping_add(full_inst.len() as i16))), None),
+                (None, Some((cs, ip))) => (Some(Operand::Far(cs, ip)), None),
+                (None, None) => (None, None),
+            },
+        };
 
-                let reg = match opcode {
-                    Opcode::MovRmToReg | Opcode::MovImmToReg => Reg::from((first_byte & 0b111) << 1 | u8::from(w)),
-                    _ => Reg::from(0b001 << 1 | u8::from(w))
-                };
+        let mnemonic = match (op_type, w) {
+            (Some(op), _) => op.to_string(),
+            (None, true) if is_string(opcode) => format!("{}W", opcode),
+            (None, false) if is_string(opcode) => format!("{}B", opcode),
+            (None, _) => opcode.to_string(),
+        };
 
-                let data = match w {
-                    true => {
-                        let full_data = u16::from(full_inst[2]) << 8 | u16::from(full_inst[1]);
-                        Some(full_data)
-                    },
-                    false => {
-                        Some(u16::from(full_inst[1]))
-                    }
-                };
+        // Memory operands without a register operand need an explicit size
+        let size = match (reg_op, rm_op, opcode) {
+            (None, Some(Operand::Mem(_)), Opcode::CallIndirectFar | Opcode::JmpIndirectFar) => Some("FAR"),
+            (None, Some(Operand::Mem(_)), opcode) if opcode != Opcode::Esc => Some(size_str(w)),
+            _ => None,
+        };
 
-                let dest = reg.to_string();
-                let source: String = match w {
-                    true => format!("{}", data.unwrap()),
-                    false => format!("{}", data.unwrap() as i8)
-                };
+        let mut inst = Instruction {
+            raw_bin,
+            len: full_inst.len(),
+            opcode,
+            d,
+            w,
+            s: op_type.map(|_| fields.s),
+            op_type,
+            segment: fields.segment,
+            lock: fields.lock,
+            rep: fields.rep,
+            dest,
+            source,
+            mnemonic,
+            size,
+            str_val: String::new(),
+        };
+        inst.str_val = inst.prefixes().into_iter()
+            .chain(Some(inst.mnemonic.as_str()))
+            .chain(inst.size)
+            .collect::<Vec<&str>>()
+            .join(" ");
+        inst
+    }
 
-                (d, w, s, mode, reg, r_m, disp_lo, disp_hi, data, dest, source, opcode.to_string())
-            },
-            Opcode::ImmToRm => {
-                let inst_len = full_inst.len();
+    fn dest(&self) -> Operand {
+        self.dest.unwrap_or_else(|| panic!("{} has no destination operand", self.opcode))
+    }
 
-                let second_byte = full_inst[1];
-                let mode_bits = (second_byte >> 6) & 0b11;
+    fn source(&self) -> Operand {
+        self.source.unwrap_or_else(|| panic!("{} has no source operand", self.opcode))
+    }
 
-                let d = false;
-                let w = (first_byte & 0b1) != 0;
-                let s = ((first_byte >> 1) & 0b1) != 0;
-                let mode =  Some(Mode::from(mode_bits));
-                let op_type =  OpType::from(second_byte >> 3 & 0b111);
-                let reg = Reg::from((second_byte >> 3 & 0b111) << 1 | u8::from(w));
-                let r_m =  Some(second_byte & 0b111);
+    /// Operation of an instruction that combines two operands through the ALU
+    fn alu_op(&self) -> Option<OpType> {
+        match self.opcode {
+            Opcode::AddRmAndReg | Opcode::AddImmToAcc => Some(OpType::ADD),
+            Opcode::OrRmAndReg | Opcode::OrImmToAcc => Some(OpType::OR),
+            Opcode::AdcRmAndReg | Opcode::AdcImmToAcc => Some(OpType::ADC),
+            Opcode::SbbRmAndReg | Opcode::SbbImmFromAcc => Some(OpType::SBB),
+            Opcode::AndRmAndReg | Opcode::AndImmToAcc => Some(OpType::AND),
+            Opcode::SubRmAndReg | Opcode::SubImmFromAcc => Some(OpType::SUB),
+            Opcode::XorRmAndReg | Opcode::XorImmToAcc => Some(OpType::XOR),
+            Opcode::CmpRmAndReg | Opcode::CmpImmToAcc => Some(OpType::CMP),
+            Opcode::ImmToRm => self.op_type,
+            _ => None,
+        }
+    }
 
-                let data = match (s, w) {
-                    (false, true) => {
-                        let full_data = u16::from(full_inst[inst_len - 1]) << 8 | u16::from(full_inst[inst_len - 2]);
-                        Some(full_data)
-                    },
-                    (false, false) | (true, true) | (true, false) => {
-                        Some(u16::from(full_inst[inst_len - 1]))
-                    }
-                };
+    /// Reads the offset and segment words of a far pointer in memory, as used by LDS/LES and indirect far CALL/JMP
+    fn far_pointer(&self, mem: &mut Memory) -> (u16, u16) {
+        let Some(Operand::Mem(mem_op)) = self.dest.filter(Operand::is_mem).or(self.source) else {
+            panic!("{} without a memory operand", self.opcode);
+        };
+        let (seg, offset) = mem_op.address(mem);
+        let ptr_offset = mem.read_mem(seg, offset, true);
+        let ptr_segment = mem.read_mem(seg, offset.wrapping_add(2), true);
+        (ptr_offset, ptr_segment)
+    }
 
-                let source = format!("{}", data.unwrap());
+    /// Jumps to the target of a relative CALL/JMP/Jcc
+    fn jump_relative(&self, mem: &mut Memory) {
+        // IP is already past this instruction and the target is relative to its start
+        let rel = self.dest().read(mem, true);
+        mem.write_ip(mem.read_ip().wrapping_sub(self.len as u16).wrapping_add(rel));
+    }
 
-                let (disp_lo, disp_hi, dest) = match Mode::from(mode_bits) {
-                    Mode::Mem => {
-                        let (disp_lo, disp_hi) = match r_m.unwrap() {
-                            0b110 => {
-                                (Some(full_inst[2]), Some(full_inst[3]))
-                            },
-                            _ => {
-                                (None, None)
-                            }
-                        };
+    /// Accumulator sized by W
+    fn acc(&self) -> Reg {
+        match self.w {
+            true => Reg::AX,
+            false => Reg::AL,
+        }
+    }
 
-                        let rm = EffectiveAddress::from(r_m.unwrap());
+    pub fn execute(&self, mem: &mut Memory) {
+        let w = self.w;
 
-                        let dest = match r_m.unwrap() {
-                            0b110 => format!("[{}]", u16::from(disp_hi.unwrap()) << 8 | u16::from(disp_lo.unwrap())),
-                            _ => format!("[{}]", rm.to_string()),
-                        };
+        match self.opcode {
+            // Data transfer
+            Opcode::MovRmToReg | Opcode::MovImmToRm | Opcode::MovImmToReg | Opcode::MovMemToAcc |
+            Opcode::MovAccToMem | Opcode::MovRmToSeg | Opcode::MovSegToRm => {
+                let val = self.source().read(mem, w);
+                self.dest().write(mem, w, val);
+            },
+            Opcode::PushRm | Opcode::PushReg | Opcode::PushSeg => {
+                // SP is decremented before PUSH SP reads it
+                let sp = mem.read_reg(Reg::SP).wrapping_sub(2);
+                mem.write_reg(Reg::SP, sp);
+                let val = self.dest().read(mem, true);
+                mem.write_mem(SegReg::SS, sp, true, val);
+            },
+            Opcode::PopRm | Opcode::PopReg | Opcode::PopSeg => {
+                let val = mem.pop();
+                self.dest().write(mem, true, val);
+            },
+            Opcode::XchgRmWithReg | Opcode::XchgRegWithAcc => {
+                let (dest, source) = (self.dest(), self.source());
+                let (a, b) = (dest.read(mem, w), source.read(mem, w));
+                dest.write(mem, w, b);
+                source.write(mem, w, a);
+            },
+            Opcode::InFixed | Opcode::InVariable => {
+                let port = self.source().read(mem, true);
+                let val = mem.read_port(port, w);
+                self.dest().write(mem, w, val);
+            },
+            Opcode::OutFixed | Opcode::OutVariable => {
+                let port = self.dest().read(mem, true);
+                let val = self.source().read(mem, w);
+                mem.write_port(port, w, val);
+            },
+            Opcode::Xlat => {
+                let offset = mem.read_reg(Reg::BX).wrapping_add(mem.read_reg(Reg::AL));
+                let val = mem.read_mem(self.segment.unwrap_or(SegReg::DS), offset, false);
+                mem.write_reg(Reg::AL, val);
+            },
+            Opcode::Lea => {
+                let Operand::Mem(mem_op) = self.source() else {
+                    panic!("LEA without a memory operand");
+                };
+                let (_, offset) = mem_op.address(mem);
+                self.dest().write(mem, true, offset);
+            },
+            Opcode::Lds | Opcode::Les => {
+                let (offset, segment) = self.far_pointer(mem);
+                self.dest().write(mem, true, offset);
+                match self.opcode {
+                    Opcode::Lds => mem.write_seg(SegReg::DS, segment),
+                    _ => mem.write_seg(SegReg::ES, segment),
+                }
+            },
+            Opcode::Lahf => mem.write_reg(Reg::AH, mem.read_flags() & 0xFF),
+            Opcode::Sahf => {
+                // Only SF, ZF, AF, PF and CF come from AH
+                let mask = [Flag::SF, Flag::ZF, Flag::AF, Flag::PF, Flag::CF].iter().fold(0, |acc, flag| acc | flag.mask());
+                let flags = (mem.read_flags() & !mask) | (mem.read_reg(Reg::AH) & mask);
+                mem.write_flags(flags);
+            },
+            Opcode::Pushf => mem.push(mem.read_flags()),
+            Opcode::Popf => {
+                let flags = mem.pop();
+                mem.write_flags(flags);
+            },
 
-                        (disp_lo, disp_hi, dest)
+            // Arithmetic and logic
+            Opcode::AddRmAndReg | Opcode::AdcRmAndReg | Opcode::SubRmAndReg | Opcode::SbbRmAndReg |
+            Opcode::CmpRmAndReg | Opcode::AndRmAndReg | Opcode::OrRmAndReg | Opcode::XorRmAndReg |
+            Opcode::AddImmToAcc | Opcode::AdcImmToAcc | Opcode::SubImmFromAcc | Opcode::SbbImmFromAcc |
+            Opcode::CmpImmToAcc | Opcode::AndImmToAcc | Opcode::OrImmToAcc | Opcode::XorImmToAcc | Opcode::ImmToRm => {
+                let op = self.alu_op().expect("ALU instruction without operation");
+
+                let (dest, source) = (self.dest(), self.source());
+                let (a, b) = (dest.read(mem, w), source.read(mem, w));
+                if let Some(val) = arith(mem, op, a, b, w) {
+                    dest.write(mem, w, val);
+                }
+            },
+            Opcode::TestRmAndReg | Opcode::TestImmToRm | Opcode::TestImmToAcc => {
+                let (a, b) = (self.dest().read(mem, w), self.source().read(mem, w));
+                alu::logic(mem, a & b, w);
+            },
+            Opcode::IncRm | Opcode::IncReg | Opcode::DecRm | Opcode::DecReg => {
+                let dest = self.dest();
+                let a = dest.read(mem, w);
+                let val = alu::inc_dec(mem, a, w, matches!(self.opcode, Opcode::IncRm | Opcode::IncReg));
+                dest.write(mem, w, val);
+            },
+            Opcode::Neg => {
+                let dest = self.dest();
+                let a = dest.read(mem, w);
+                let val = alu::neg(mem, a, w);
+                dest.write(mem, w, val);
+            },
+            Opcode::Not => {
+                let dest = self.dest();
+                let a = dest.read(mem, w);
+                dest.write(mem, w, !a);
+            },
+            Opcode::Mul | Opcode::Imul => {
+                let b = self.dest().read(mem, w);
+                let (hi, lo) = alu::mul(mem, mem.read_reg(Reg::AX), b, w, self.opcode == Opcode::Imul);
+                match w {
+                    true => {
+                        mem.write_reg(Reg::AX, lo);
+                        mem.write_reg(Reg::DX, hi);
                     },
-                    Mode::Mem8 => {
-                        let disp_lo: Option<u8> = Some(full_inst[2]);
-                        let disp_hi: Option<u8> =  None;
-                        let rm = EffectiveAddress::from(r_m.unwrap());
-                        let dest = format!("[{} + {}]", rm.to_string(), disp_lo.unwrap());
+                    false => mem.write_reg(Reg::AX, hi << 8 | lo),
+                }
+            },
+            Opcode::Div | Opcode::Idiv => {
+                let b = self.dest().read(mem, w);
+                let hi = match w {
+                    true => mem.read_reg(Reg::DX),
+                    false => 0,
+                };
 
-                        (disp_lo, disp_hi, dest)
+                match (alu::div(hi, mem.read_reg(Reg::AX), b, w, self.opcode == Opcode::Idiv), w) {
+                    // Divide error
+                    (None, _) => mem.interrupt(0),
+                    (Some((quotient, remainder)), true) => {
+                        mem.write_reg(Reg::AX, quotient);
+                        mem.write_reg(Reg::DX, remainder);
                     },
-                    Mode::Mem16 => {
-                        let disp_lo = Some(full_inst[2]);
-                        let disp_hi = Some(full_inst[3]);
-                        let full_disp = u16::from(disp_hi.unwrap()) << 8 | u16::from(disp_lo.unwrap());
-                        let rm = EffectiveAddress::from(r_m.unwrap());
-                        let dest = format!("[{} + {}]", rm.to_string(), full_disp);
-
-                        (disp_lo, disp_hi, dest)
+                    (Some((quotient, remainder)), false) => {
+                        mem.write_reg(Reg::AL, quotient);
+                        mem.write_reg(Reg::AH, remainder);
                     },
-                    Mode::Reg => {
-                        let disp_lo = None;
-                        let disp_hi = None;
-                        let rm_reg = Reg::from(r_m.unwrap() << 1 | u8::from(w));
-                        let dest = rm_reg.to_string();
-
-                        (disp_lo, disp_hi, dest)
-                    }
+                }
+            },
+            Opcode::Aaa | Opcode::Aas => {
+                let ax = alu::ascii_adjust(mem, mem.read_reg(Reg::AX), self.opcode == Opcode::Aas);
+                mem.write_reg(Reg::AX, ax);
+            },
+            Opcode::Daa | Opcode::Das => {
+                let al = alu::decimal_adjust(mem, mem.read_reg(Reg::AL) as u8, self.opcode == Opcode::Das);
+                mem.write_reg(Reg::AL, u16::from(al));
+            },
+            Opcode::Aam => {
+                let al = mem.read_reg(Reg::AL);
+                mem.write_reg(Reg::AH, al / 10);
+                mem.write_reg(Reg::AL, al % 10);
+                alu::set_szp(mem, al % 10, false);
+            },
+            Opcode::Aad => {
+                let al = (mem.read_reg(Reg::AH) * 10 + mem.read_reg(Reg::AL)) & 0xFF;
+                mem.write_reg(Reg::AX, al);
+                alu::set_szp(mem, al, false);
+            },
+            Opcode::Cbw => mem.write_reg(Reg::AX, mem.read_reg(Reg::AL) as u8 as i8 as u16),
+            Opcode::Cwd => {
+                let dx = match mem.read_reg(Reg::AX) & 0x8000 {
+                    0 => 0,
+                    _ => 0xFFFF,
+                };
+                mem.write_reg(Reg::DX, dx);
+            },
+            Opcode::Shl | Opcode::Shr | Opcode::Sar | Opcode::Rol | Opcode::Ror | Opcode::Rcl | Opcode::Rcr => {
+                let op = match self.opcode {
+                    Opcode::Shl => alu::Shift::Shl,
+                    Opcode::Shr => alu::Shift::Shr,
+                    Opcode::Sar => alu::Shift::Sar,
+                    Opcode::Rol => alu::Shift::Rol,
+                    Opcode::Ror => alu::Shift::Ror,
+                    Opcode::Rcl => alu::Shift::Rcl,
+                    _ => alu::Shift::Rcr,
                 };
+                let count = self.source().read(mem, false) as u8;
 
-                let mut str_val = op_type.to_string();
+                let dest = self.dest();
+                let a = dest.read(mem, w);
+                let val = alu::shift(mem, op, a, count, w);
+                dest.write(mem, w, val);
+            },
 
-                match mode.unwrap() {
-                    Mode::Reg => {},
-                    _ => {
-                        match w {
-                            true => str_val.push_str(" WORD"),
-                            false => str_val.push_str(" BYTE"),
-                        }
-                    }
+            // String manipulation
+            Opcode::Movs | Opcode::Cmps | Opcode::Scas | Opcode::Lods | Opcode::Stos => self.string_op(mem),
+
+            // Control transfer
+            Opcode::CallDirect => {
+                mem.push(mem.read_ip());
+                self.jump_relative(mem);
+            },
+            Opcode::CallIndirect => {
+                let target = self.dest().read(mem, true);
+                mem.push(mem.read_ip());
+                mem.write_ip(target);
+            },
+            Opcode::CallDirectFar | Opcode::CallIndirectFar => {
+                let (ip, cs) = match self.dest() {
+                    Operand::Far(cs, ip) => (ip, cs),
+                    _ => self.far_pointer(mem),
+                };
+                mem.push(mem.read_seg(SegReg::CS));
+                mem.push(mem.read_ip());
+                mem.write_seg(SegReg::CS, cs);
+                mem.write_ip(ip);
+            },
+            Opcode::JmpDirect | Opcode::JmpShort => self.jump_relative(mem),
+            Opcode::JmpIndirect => {
+                let target = self.dest().read(mem, true);
+                mem.write_ip(target);
+            },
+            Opcode::JmpDirectFar | Opcode::JmpIndirectFar => {
+                let (ip, cs) = match self.dest() {
+                    Operand::Far(cs, ip) => (ip, cs),
+                    _ => self.far_pointer(mem),
+                };
+                mem.write_seg(SegReg::CS, cs);
+                mem.write_ip(ip);
+            },
+            Opcode::Ret | Opcode::RetImm | Opcode::RetFar | Opcode::RetFarImm => {
+                let ip = mem.pop();
+                mem.write_ip(ip);
+                if let Opcode::RetFar | Opcode::RetFarImm = self.opcode {
+                    let cs = mem.pop();
+                    mem.write_seg(SegReg::CS, cs);
+                }
+                if let Some(Operand::Imm(imm)) = self.dest {
+                    mem.write_reg(Reg::SP, mem.read_reg(Reg::SP).wrapping_add(imm.value));
                 }
-                (d, w, Some(s), mode, reg, r_m, disp_lo, disp_hi, data, dest, source, str_val)
             },
             Opcode::JmpEqual | Opcode::JmpLess| Opcode::JmpLessOrEqual | Opcode::JmpBelow | Opcode::JmpBelowOrEqual |
             Opcode::JmpParity | Opcode::JmpOverflow | Opcode::JmpSign | Opcode::JmpNotEqual | Opcode::JmpNotLess | Opcode::JmpNotLessOrEqual |
             Opcode::JmpNotBelow | Opcode::JmpNotBelowOrEqual | Opcode::JmpNotParity | Opcode::JmpNotOverflow | Opcode::JmpOnNotSign |
             Opcode::Loop | Opcode::LoopZero | Opcode::LoopNotZero | Opcode::JmpCXZero => {
-                let d = false;
-                let w = false;
-                let s = None;
-                let mode = None;
-                let reg = Reg::UNIMPL; // NOTE: If there are issues, it may be here. Confirmed this is always showing as 'AX'
-                let r_m = None;
-                let disp_lo = None;
-                let disp_hi = None;
-                let data = None;
-                let dest = String::from("label");
-
-                let source = format!("{}", full_inst[1] as i8);
-
-                (d, w, s, mode, reg, r_m, disp_lo, disp_hi, data, dest, source, opcode.to_string())
-            },
-            Opcode::Unimpl => {
-                panic!("YOU SHOULDN'T SEE THIS");
-                // (0, 0, None, None, 0b000, 0b000, None, None, None)
-            }
+                let zf = mem.get_flag(Flag::ZF);
+                let sf = mem.get_flag(Flag::SF);
+                let of = mem.get_flag(Flag::OF);
+                let cf = mem.get_flag(Flag::CF);
+                let pf = mem.get_flag(Flag::PF);
+
+                let taken = match self.opcode {
+                    Opcode::JmpEqual           => zf,
+                    Opcode::JmpLess            => sf != of,
+                    Opcode::JmpLessOrEqual     => zf || sf != of,
+                    Opcode::JmpBelow           => cf,
+                    Opcode::JmpBelowOrEqual    => cf || zf,
+                    Opcode::JmpParity          => pf,
+                    Opcode::JmpOverflow        => of,
+                    Opcode::JmpSign            => sf,
+                    Opcode::JmpNotEqual        => !zf,
+                    Opcode::JmpNotLess         => sf == of,
+                    Opcode::JmpNotLessOrEqual  => !zf && sf == of,
+                    Opcode::JmpNotBelow        => !cf,
+                    Opcode::JmpNotBelowOrEqual => !cf && !zf,
+                    Opcode::JmpNotParity       => !pf,
+                    Opcode::JmpNotOverflow     => !of,
+                    Opcode::JmpOnNotSign       => !sf,
+                    Opcode::JmpCXZero          => mem.read_reg(Reg::CX) == 0,
+                    _ => {
+                        // LOOP family decrements CX without touching flags
+                        let cx = mem.read_reg(Reg::CX).wrapping_sub(1);
+                        mem.write_reg(Reg::CX, cx);
+                        match self.opcode {
+                            Opcode::LoopZero => cx != 0 && zf,
+                            Opcode::LoopNotZero => cx != 0 && !zf,
+                            _ => cx != 0,
+                        }
+                    }
+                };
+
+                if taken {
+                    self.jump_relative(mem);
+                }
+            },
+            Opcode::Int => {
+                let vector = self.dest().read(mem, false) as u8;
+                mem.interrupt(vector);
+            },
+            Opcode::Int3 => mem.interrupt(3),
+            Opcode::Into => {
+                if mem.get_flag(Flag::OF) {
+                    mem.interrupt(4);
+                }
+            },
+            Opcode::Iret => {
+                let ip = mem.pop();
+                let cs = mem.pop();
+                let flags = mem.pop();
+                mem.write_ip(ip);
+                mem.write_seg(SegReg::CS, cs);
+                mem.write_flags(flags);
+            },
+
+            // Processor control
+            Opcode::Clc => mem.set_flag(Flag::CF, false),
+            Opcode::Cmc => mem.set_flag(Flag::CF, !mem.get_flag(Flag::CF)),
+            Opcode::Stc => mem.set_flag(Flag::CF, true),
+            Opcode::Cld => mem.set_flag(Flag::DF, false),
+            Opcode::Std => mem.set_flag(Flag::DF, true),
+            Opcode::Cli => mem.set_flag(Flag::IF, false),
+            Opcode::Sti => mem.set_flag(Flag::IF, true),
+            // HLT is handled by the CPU, and there is no coprocessor for WAIT and ESC
+            Opcode::Hlt | Opcode::Wait | Opcode::Esc => {},
+            Opcode::Lock | Opcode::Rep | Opcode::Segment | Opcode::Unimpl => panic!("{} is not an instruction", self.opcode),
+        }
+    }
+
+    /// MOVS/CMPS/SCAS/LODS/STOS, repeated while CX is non-zero under a REP prefix
+    fn string_op(&self, mem: &mut Memory) {
+        let w = self.w;
+        let step: u16 = match (w, mem.get_flag(Flag::DF)) {
+            (false, false) => 1,
+            (true, false) => 2,
+            (false, true) => 1u16.wrapping_neg(),
+            (true, true) => 2u16.wrapping_neg(),
         };
+        // Only the source segment can be overridden; the destination is always ES:DI
+        let src_seg = self.segment.unwrap_or(SegReg::DS);
 
+        loop {
+            if self.rep.is_some() && mem.read_reg(Reg::CX) == 0 {
+                break;
+            }
 
-        Instruction {
-            raw_bin,
-            opcode,
-            d,
-            w,
-            s,
-            mode,
-            reg,
-            r_m,
-            disp_lo,
-            disp_hi,
-            data,
-            dest,
-            source,
-            str_val
+            let si = mem.read_reg(Reg::SI);
+            let di = mem.read_reg(Reg::DI);
+            match self.opcode {
+                Opcode::Movs => {
+                    let val = mem.read_mem(src_seg, si, w);
+                    mem.write_mem(SegReg::ES, di, w, val);
+                },
+                Opcode::Cmps => {
+                    let a = mem.read_mem(src_seg, si, w);
+                    let b = mem.read_mem(SegReg::ES, di, w);
+                    alu::sub(mem, a, b, w, false);
+                },
+                Opcode::Scas => {
+                    let b = mem.read_mem(SegReg::ES, di, w);
+                    alu::sub(mem, mem.read_reg(self.acc()), b, w, false);
+                },
+                Opcode::Lods => {
+                    let val = mem.read_mem(src_seg, si, w);
+                    mem.write_reg(self.acc(), val);
+                },
+                _ => mem.write_mem(SegReg::ES, di, w, mem.read_reg(self.acc())),
+            }
+
+            if let Opcode::Movs | Opcode::Cmps | Opcode::Lods = self.opcode {
+                mem.write_reg(Reg::SI, si.wrapping_add(step));
+            }
+            if let Opcode::Movs | Opcode::Cmps | Opcode::Scas | Opcode::Stos = self.opcode {
+                mem.write_reg(Reg::DI, di.wrapping_add(step));
+            }
+
+            let Some(while_zero) = self.rep else {
+                break;
+            };
+            mem.write_reg(Reg::CX, mem.read_reg(Reg::CX).wrapping_sub(1));
+
+            // REPE/REPNE stop CMPS and SCAS early
+            if let Opcode::Cmps | Opcode::Scas = self.opcode {
+                if mem.get_flag(Flag::ZF) != while_zero {
+                    break;
+                }
+            }
         }
     }
+}
 
-    pub fn execute(self, mem: &mut Memory) {
-        match self.opcode {
-            Opcode::MovImmToReg => {
-                // Get value to write
-                let val = self.source.parse::<u8>().unwrap(); // TODO: error handling
+/// Performs an arithmetic or logic operation, setting flags. Returns the value to write back, if any.
+fn arith(mem: &mut Memory, op: OpType, dest: u16, source: u16, w: bool) -> Option<u16> {
+    let cf = mem.get_flag(Flag::CF);
+    match op {
+        OpType::ADD => Some(alu::add(mem, dest, source, w, false)),
+        OpType::ADC => Some(alu::add(mem, dest, source, w, cf)),
+        OpType::SUB => Some(alu::sub(mem, dest, source, w, false)),
+        OpType::SBB => Some(alu::sub(mem, dest, source, w, cf)),
+        OpType::AND => Some(alu::logic(mem, dest & source, w)),
+        OpType::OR => Some(alu::logic(mem, dest | source, w)),
+        OpType::XOR => Some(alu::logic(mem, dest ^ source, w)),
+        OpType::CMP => {
+            alu::sub(mem, dest, source, w, false);
+            None
+        },
+    }
+}
 
-                // Get mem loc
-                let loc: &mut MemLoc = mem.get_loc(self.dest.as_str())
-                    .expect("No memory location found!"); // TODO: print out actual location
+/// Memory operand of a MOD R/M pair, with its displacement sign-extended
+fn mem_operand(mode: Mode, r_m: u8, fields: &Fields) -> MemOperand {
+    let disp_lo = fields.disp_lo.unwrap_or(0);
+    let disp16 = u16::from(fields.disp_hi.unwrap_or(0)) << 8 | u16::from(disp_lo);
+
+    let (ea, disp) = match (mode, r_m) {
+        (Mode::Mem, 0b110) => (EffectiveAddress::Direct, Some(disp16)),
+        (Mode::Mem8, _) => (EffectiveAddress::from(r_m), Some(disp_lo as i8 as u16)),
+        (Mode::Mem16, _) => (EffectiveAddress::from(r_m), Some(disp16)),
+        _ => (EffectiveAddress::from(r_m), None),
+    };
+
+    MemOperand {
+        ea,
+        disp,
+        disp16: fields.disp_hi.is_some(),
+        segment: fields.segment,
+    }
+}
 
-                loc.write(val);
-            },
-            Opcode::MovRmToReg => {
-                // Get value to write
-                let val = mem.clone().read_loc(self.source.as_str());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode;
 
-                // Get destination loc
-                let dest_loc: &mut MemLoc = mem.get_loc(self.dest.as_str())
-                    .expect("Destination memory location not found!"); // TODO: print out actual location
+    /// Executes `bytes` at CS:0000 with `flags` set and CX given, returning IP and CX after
+    fn branch(bytes: &[u8], flags: &[Flag], cx: u16) -> (u16, u16) {
+        let mut mem = Memory::new();
+        for flag in flags {
+            mem.set_flag(*flag, true);
+        }
+        mem.write_reg(Reg::CX, cx);
+        let inst = decode(bytes).unwrap();
+        mem.write_ip(inst.len as u16);
+        let before = mem.flags_string();
+        inst.execute(&mut mem);
+        assert_eq!(mem.flags_string(), before, "{:02X?} changed flags", bytes);
+        (mem.read_ip(), mem.read_reg(Reg::CX))
+    }
 
-                dest_loc.write(val);
-            },
-            _ => todo!(),
+    #[test]
+    fn conditional_jumps_follow_flags() {
+        use Flag::*;
+        // Jcc +4: taken lands on 6, not taken on 2
+        let cases: [(u8, &[Flag], bool); 30] = [
+            (0x70, &[OF], true), (0x70, &[], false),
+            (0x71, &[], true), (0x71, &[OF], false),
+            (0x72, &[CF], true), (0x72, &[ZF], false),
+            (0x73, &[ZF], true), (0x73, &[CF], false),
+            (0x74, &[ZF], true), (0x74, &[CF], false),
+            (0x75, &[CF], true), (0x75, &[ZF], false),
+            (0x76, &[ZF], true), (0x76, &[CF], true), (0x76, &[SF], false),
+            (0x77, &[SF], true), (0x77, &[CF], false),
+            (0x78, &[SF], true), (0x79, &[SF], false),
+            (0x7A, &[PF], true), (0x7B, &[PF], false),
+            (0x7C, &[SF], true), (0x7C, &[SF, OF], false),
+            (0x7D, &[SF, OF], true), (0x7D, &[OF], false),
+            (0x7E, &[ZF, SF, OF], true), (0x7E, &[], false),
+            (0x7F, &[], true), (0x7F, &[ZF], false), (0x7F, &[OF], false),
+        ];
+        for (opcode, flags, taken) in cases {
+            let ip = branch(&[opcode, 0x04], flags, 0).0;
+            assert_eq!(ip, if taken { 6 } else { 2 }, "{:02X} with {:?}", opcode, flags);
         }
+
+        // Backwards, wrapping below 0
+        assert_eq!(branch(&[0x74, 0xFC], &[ZF], 0).0, 0xFFFE);
+    }
+
+    #[test]
+    fn loops_decrement_cx_before_testing_it() {
+        use Flag::*;
+        // LOOP $: CX=0 wraps to FFFFh and keeps looping
+        assert_eq!(branch(&[0xE2, 0xFE], &[], 0), (0, 0xFFFF));
+        assert_eq!(branch(&[0xE2, 0xFE], &[], 2), (0, 1));
+        assert_eq!(branch(&[0xE2, 0xFE], &[], 1), (2, 0));
+
+        // LOOPZ and LOOPNZ also need ZF set or clear
+        assert_eq!(branch(&[0xE1, 0xFE], &[ZF], 2), (0, 1));
+        assert_eq!(branch(&[0xE1, 0xFE], &[], 2), (2, 1));
+        assert_eq!(branch(&[0xE1, 0xFE], &[ZF], 1), (2, 0));
+        assert_eq!(branch(&[0xE0, 0xFE], &[], 2), (0, 1));
+        assert_eq!(branch(&[0xE0, 0xFE], &[ZF], 2), (2, 1));
+
+        // JCXZ tests CX without changing it
+        assert_eq!(branch(&[0xE3, 0x10], &[], 0), (0x12, 0));
+        assert_eq!(branch(&[0xE3, 0x10], &[], 1), (2, 1));
     }
-}
\ No newline at end of file
+}
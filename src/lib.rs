@@ -0,0 +1,24 @@
+#![allow(dead_code, unused_variables, non_camel_case_types, clippy::upper_case_acronyms)]
+
+mod alu;
+pub mod bus;
+pub mod clocks;
+pub mod cpu;
+pub mod debugger;
+pub mod decode;
+pub mod disasm;
+pub mod dos;
+pub mod exe;
+pub mod gdb;
+pub mod instruction;
+pub mod mem;
+pub mod operand;
+pub mod trace;
+pub mod video;
+pub mod watch;
+
+pub use clocks::Model;
+pub use cpu::{Cpu, Step};
+pub use decode::{decode, DecodeError};
+pub use exe::{Exe, ExeError};
+pub use instruction::Instruction;
@@ -1,18 +1,44 @@
-#![allow(dead_code, unused_variables, non_camel_case_types)]
-
-mod instruction;
-mod mem;
-
-use instruction::*;
+use sim86::disasm::{disassemble, disassemble_recursive};
+use sim86::debugger::Debugger;
+use sim86::dos::Dos;
+use sim86::gdb::GdbServer;
+use sim86::mem::{Memory, MEM_SIZE};
+use sim86::trace::{json_condition_stop, json_record, json_watch_stop, Before};
+use sim86::video::{screen_ansi, screen_text};
+use sim86::watch::{Condition, Trigger, Watchpoint};
+use sim86::{decode, Cpu, DecodeError, Exe, Model, Step};
 use std::fs;
-use std::convert::From;
 use std::env;
+use std::io::{self, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use mem::Memory;
 
-// TODO:
-//   - Finish implementing commented out opcodes?
+/// Words accepted before the filepath
+const FLAGS: [&str; 16] = ["debug", "bindump", "file", "abort", "disasm", "recursive", "clocks", "8088", "bus", "busfetch", "com", "exe", "screen", "ansi", "memdump", "json"];
+
+/// Options accepted before the filepath that take a value, as `name=value`
+const OPTIONS: [&str; 5] = ["sandbox", "memdump", "listen", "watch", "break"];
+
+/// Where `gdb-server` listens unless given `listen=`
+const DEFAULT_GDB_ADDRESS: &str = "127.0.0.1:1234";
+
+fn is_flag(arg: &str) -> bool {
+    FLAGS.contains(&arg) || OPTIONS.iter().any(|name| arg.strip_prefix(name).is_some_and(|rest| rest.starts_with('=')))
+}
+
+/// Value of a `name=value` option
+fn option<'a>(flags: &[&'a str], name: &str) -> Option<&'a str> {
+    options(flags, name).first().copied()
+}
 
+/// Values of a `name=value` option that can be given more than once
+fn options<'a>(flags: &[&'a str], name: &str) -> Vec<&'a str> {
+    flags.iter().filter_map(|flag| flag.strip_prefix(name)?.strip_prefix('=')).collect()
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -22,21 +48,71 @@ fn main() {
         exit(1);
     }
 
-    let filepath = args.last().expect("No filename provided");
+    // An optional subcommand, then flags; the first other argument is the filepath and the rest are its command tail
+    let repl = args[1] == "debug-repl";
+    let gdb_server = args[1] == "gdb-server";
+    let flags_idx = if repl || gdb_server { 2 } else { 1 };
+    let path_idx = args.iter().skip(flags_idx).position(|arg| !is_flag(arg)).map(|idx| idx + flags_idx);
+    let Some(path_idx) = path_idx else {
+        eprintln!("You need to provide a filepath!");
+        exit(1);
+    };
+    let filepath = &args[path_idx];
+    let tail: String = args[path_idx + 1..].iter().map(|arg| format!(" {}", arg)).collect();
 
-    let mut flags: Vec<&str> = Vec::new();
-    if args.len() > 2 {
-        for idx in 1..(args.len() - 1) {
-            flags.push(&args[idx]);
-        }
-    }
+    let flags: Vec<&str> = args[flags_idx..path_idx].iter().map(String::as_str).collect();
 
     let debug = flags.contains(&"debug");
     let bindump = flags.contains(&"bindump");
     let to_file = flags.contains(&"file");
+    let abort = flags.contains(&"abort");
+    let recursive = flags.contains(&"recursive");
+    let disasm = flags.contains(&"disasm") || recursive;
+    let i8088 = flags.contains(&"8088");
+    let show_clocks = flags.contains(&"clocks") || i8088;
+    let bus_fetch = flags.contains(&"busfetch");
+    let show_bus = flags.contains(&"bus") || bus_fetch;
+    let com = flags.contains(&"com") || filepath.to_lowercase().ends_with(".com");
+    // Whole 1 MiB unless a range of physical addresses is given
+    let memdump = match (flags.contains(&"memdump"), option(&flags, "memdump")) {
+        (_, Some(range)) => match parse_range(range) {
+            Some(range) => Some(range),
+            None => {
+                eprintln!("Invalid memdump range {range}: expected START-END in hex, within 0-{:X}", MEM_SIZE);
+                exit(1);
+            }
+        },
+        (true, None) => Some(0..MEM_SIZE),
+        (false, None) => None,
+    };
+    let json = flags.contains(&"json");
+    let ansi = flags.contains(&"ansi");
+    let show_screen = flags.contains(&"screen") || ansi;
+    let is_exe = flags.contains(&"exe") || filepath.to_lowercase().ends_with(".exe");
+    // DOS file access is confined to the program's own directory unless told otherwise
+    let sandbox = match option(&flags, "sandbox") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(filepath).parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    // Stop the trace when a watched address is accessed or a condition becomes true
+    let parsed: Result<Vec<Watchpoint>, String> = options(&flags, "watch").into_iter().map(str::parse).collect();
+    let watchpoints = parsed.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        exit(1);
+    });
+    let parsed: Result<Vec<Condition>, String> = options(&flags, "break").into_iter().map(str::parse).collect();
+    let conditions = parsed.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        exit(1);
+    });
+    // GDB sets its own watchpoints and breakpoints
+    if gdb_server && !(watchpoints.is_empty() && conditions.is_empty()) {
+        eprintln!("watch= and break= aren't supported by gdb-server; set them from GDB instead");
+        exit(1);
+    }
 
     // If output, create dir if it doesn't exist
-    if debug || bindump || to_file {
+    if debug || bindump || to_file || memdump.is_some() {
         fs::create_dir_all("output").expect("Failed to create output directory");
     }
 
@@ -53,10 +129,10 @@ fn main() {
             hex_str.push_str(&format!("{:02X} ", byte_val));
             bin_str.push_str(&format!("{:08b} ", byte_val));
             if idx % 8 == 0 {
-                bin_str.push_str("\n");
+                bin_str.push('\n');
             }
             if idx % 16 == 0 {
-                hex_str.push_str("\n");
+                hex_str.push('\n');
             }
             idx += 1;
         }
@@ -65,299 +141,291 @@ fn main() {
         fs::write("output/orig_bytes.txt", bin_str).expect("Failed to write orig bin file.");
     }
 
-    // Initialize Memory
-    let mut main_mem = Memory::new();
-
-    let mut debug_output = String::new(); // For debug file
+    let exe = match is_exe {
+        true => match Exe::parse(&buffer) {
+            Ok(exe) => Some(exe),
+            Err(err) => {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        },
+        false => None,
+    };
+
+    if disasm {
+        // Executables are disassembled without their header, from the entry point
+        let (code, entry) = match &exe {
+            Some(exe) => (exe.module, exe.entry()),
+            None => (&buffer[..], 0),
+        };
+        let (asm_output, errors) = match recursive {
+            true => disassemble_recursive(code, entry),
+            false => disassemble(code, entry),
+        };
+        for err in &errors {
+            eprintln!("{}", err);
+        }
+        if abort && !errors.is_empty() {
+            exit(1);
+        }
 
-    let instructions: Vec<Instruction> = read_buffer_into_instructions(buffer, bindump, &mut debug_output);
+        println!("{asm_output}");
+        if to_file {
+            fs::write("output/output.asm", asm_output).expect("Unable to write ASM file");
+        }
+        return;
+    }
 
     if debug {
-        fs::write("output/debug_output.txt", &debug_output).expect("Failed to write debug output file.");
+        fs::write("output/debug_output.txt", debug_listing(&buffer)).expect("Failed to write debug output file.");
+    }
+
+    let mut cpu = Cpu::new();
+    cpu.dos = Dos::new(sandbox);
+    match (&exe, com) {
+        (Some(exe), _) => cpu.load_exe(exe, &tail).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(1);
+        }),
+        (None, true) => cpu.load_com(&buffer, &tail),
+        (None, false) => cpu.load(&buffer),
+    }
+    if i8088 {
+        cpu.model = Model::I8088;
+    }
+    cpu.count_fetch = bus_fetch;
+
+    if repl {
+        let mut debugger = Debugger::new(cpu);
+        for watchpoint in watchpoints {
+            debugger.add_watchpoint(watchpoint);
+        }
+        for condition in conditions {
+            debugger.add_condition(condition);
+        }
+        debugger.run(&mut io::stdin().lock(), &mut io::stdout()).expect("Failed to run debugger");
+        return;
+    }
+
+    if gdb_server {
+        let listen = option(&flags, "listen").unwrap_or(DEFAULT_GDB_ADDRESS);
+        serve_gdb(GdbServer::new(cpu), listen).expect("Failed to run GDB server");
+        return;
     }
 
+    let mut triggers: Vec<Trigger> = conditions.into_iter().map(|condition| Trigger::new(condition, &cpu.mem)).collect();
     let mut asm_output = String::from("bits 16\n");
+    // JSON Lines are written as they're produced, so endless programs still trace
+    let mut trace_file = (json && to_file).then(|| fs::File::create("output/trace.jsonl").expect("Unable to create trace file"));
+
+    while cpu.running() {
+        // Snapshot registers so any that change can be written to output
+        let pre_regs = cpu.mem.registers();
+        let pre_flags = cpu.mem.flags_string();
+        let before = json.then(|| Before::new(&cpu.mem));
+
+        let step = match cpu.step() {
+            Ok(step) => step,
+            Err(err) => {
+                eprintln!("{}", err);
+                if abort {
+                    exit(1);
+                }
 
-    for inst in instructions {
-        let mem_loc_string = inst.dest.clone();
+                // Skip the offending byte and try again from the next one
+                cpu.mem.write_ip(cpu.mem.read_ip().wrapping_add(1));
+                continue;
+            }
+        };
 
-        // Write to output
-        let inst_string = format!("{} {}, {} ; ", inst.str_val, &mem_loc_string, inst.source);
+        if let Some(before) = &before {
+            write_json(&json_record(before, &step, &cpu.mem), &mut trace_file);
+        }
 
-        // Write current mem val to output
-        let pre_mem_string = format!("{}:0x{:02x}", &mem_loc_string, main_mem.clone().read_loc(&mem_loc_string));
+        let watched = watchpoints.iter().find_map(|watchpoint| Some((watchpoint, watchpoint.hit(&cpu.mem.accesses)?)));
+        // Every trigger is updated, so one that rose alongside another doesn't fire a step late
+        let mut condition = None;
+        for trigger in &mut triggers {
+            if trigger.fired(&cpu.mem) && condition.is_none() {
+                condition = Some(&trigger.condition);
+            }
+        }
+        if json {
+            if let Some((watchpoint, access)) = watched {
+                write_json(&json_watch_stop(watchpoint, access), &mut trace_file);
+                break;
+            }
+            if let Some(condition) = condition {
+                write_json(&json_condition_stop(condition), &mut trace_file);
+                break;
+            }
+            continue;
+        }
 
+        // Write to output
+        let Step { inst, clocks, transfers } = step;
+        let inst_string = format!("{} ; ", inst);
         asm_output.push_str(&inst_string);
-        asm_output.push_str(&pre_mem_string);
+        if show_clocks {
+            asm_output.push_str(&format!("Clocks: +{} = {} | ", clocks, cpu.clocks));
+        }
+        if show_bus {
+            asm_output.push_str(&format!("Transfers: +{} = {} | ", transfers, cpu.transfers.total()));
+        }
+
+        // Write changed register vals to output
+        for ((reg, pre_val), post_val) in Memory::loc_list().iter().zip(pre_regs).zip(cpu.mem.registers()) {
+            if post_val != pre_val {
+                asm_output.push_str(&format!("{}:0x{:04x}->0x{:04x} ", reg, pre_val, post_val));
+            }
+        }
 
-        // Execute any MOV instructions
-        match inst.opcode {
-            Opcode::MovImmToReg => inst.execute(&mut main_mem),
-            Opcode::MovRmToReg => inst.execute(&mut main_mem),
-            _ => todo!("Only MovImmToReg and MovRmToReg implemented"),
+        let post_flags = cpu.mem.flags_string();
+        if post_flags != pre_flags {
+            asm_output.push_str(&format!("FLAGS:{}->{} ", pre_flags, post_flags));
         }
+        asm_output.push('\n');
 
-        // Write new mem val to output
-        let post_mem_string = format!("->{}:0x{:02x}\n", &mem_loc_string, main_mem.clone().read_loc(&mem_loc_string));
-        asm_output.push_str(&post_mem_string);
+        if let Some((watchpoint, access)) = watched {
+            let kind = if access.write { "write" } else { "read" };
+            asm_output.push_str(&format!("; Stopped at watchpoint {}: {} at 0x{:05x}\n", watchpoint, kind, access.addr));
+            break;
+        }
+        if let Some(condition) = condition {
+            asm_output.push_str(&format!("; Stopped on condition: {}\n", condition));
+            break;
+        }
     }
 
-    // Add final state of all changed registers to output
-    asm_output.push_str("\n\n; Final Registers:\n");
-    for reg in Memory::loc_list() {
-        let reg_val = &main_mem.clone().read_loc(&reg);
-        let reg_str = format!(";    {}: 0x{:04x} ({})\n", reg, reg_val, reg_val);
-        asm_output.push_str(&reg_str);
+    if !json {
+        // Add final state of all changed registers to output
+        asm_output.push_str("\n\n; Final Registers:\n");
+        for (reg, reg_val) in Memory::loc_list().iter().zip(cpu.mem.registers()) {
+            let reg_str = format!(";    {}: 0x{:04x} ({})\n", reg, reg_val, reg_val);
+            asm_output.push_str(&reg_str);
+        }
+        asm_output.push_str(&format!(";    FLAGS: {}\n", cpu.mem.flags_string()));
+        if show_clocks {
+            asm_output.push_str(&format!("\n; Total clocks ({}): {}\n", cpu.model, cpu.clocks));
+        }
+        if show_bus {
+            let total = cpu.transfers;
+            asm_output.push_str(&format!("\n; Bus transfers ({}): {}\n", cpu.model, total.total()));
+            if cpu.count_fetch {
+                asm_output.push_str(&format!(";    Fetch: {}\n", total.fetch));
+            }
+            asm_output.push_str(&format!(";    Reads: {}\n;    Writes: {}\n", total.reads, total.writes));
+            asm_output.push_str(&format!(";    Odd-address word accesses: {}\n", total.odd_words));
+        }
+
+        println!("{asm_output}");
+
+        if to_file {
+            fs::write("output/output.asm", asm_output).expect("Unable to write ASM file");
+        }
+    }
+
+    if let Some(range) = memdump {
+        fs::write("output/memory.data", cpu.mem.dump(range)).expect("Failed to write memory dump file.");
     }
 
-    println!("{asm_output}");
+    if show_screen {
+        let screen = match ansi {
+            true => screen_ansi(&cpu.mem),
+            false => screen_text(&cpu.mem),
+        };
+        // Keep stdout valid JSON Lines
+        match json {
+            true => eprintln!("; Screen:\n{screen}"),
+            false => println!("; Screen:\n{screen}"),
+        }
+    }
+
+    if let Some(code) = cpu.dos.exit_code {
+        exit(code.into());
+    }
+}
+
+/// Prints one JSON Lines record, and appends it to the trace file if there is one
+fn write_json(record: &str, trace_file: &mut Option<fs::File>) {
+    println!("{record}");
+    if let Some(file) = trace_file {
+        writeln!(file, "{record}").expect("Unable to write trace file");
+    }
+}
 
-    if to_file {
-        fs::write("output/output.asm", asm_output).expect("Unable to write ASM file");
+/// Waits for one GDB connection on a TCP address or `unix:PATH` socket and serves it
+fn serve_gdb(mut server: GdbServer, listen: &str) -> io::Result<()> {
+    if let Some(path) = listen.strip_prefix("unix:") {
+        #[cfg(unix)]
+        {
+            let listener = UnixListener::bind(path)?;
+            eprintln!("Waiting for GDB on {}", path);
+            let (mut conn, _) = listener.accept()?;
+            let result = server.serve(&mut conn);
+            fs::remove_file(path)?;
+            return result;
+        }
+        #[cfg(not(unix))]
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets aren't available on this platform"));
     }
+
+    let listener = TcpListener::bind(listen)?;
+    eprintln!("Waiting for GDB on {}", listener.local_addr()?);
+    let (mut conn, _) = listener.accept()?;
+    conn.set_nodelay(true)?;
+    server.serve(&mut conn)
 }
 
-fn read_buffer_into_instructions(buffer: Vec<u8>, debug: bool, debug_output: &mut String) -> Vec<Instruction> {
-    let mut instructions: Vec<Instruction> = Vec::new();
+/// Parses `START-END` hex physical addresses, END exclusive
+fn parse_range(range: &str) -> Option<Range<usize>> {
+    let (start, end) = range.split_once('-')?;
+    let parse = |addr: &str| usize::from_str_radix(addr.trim_start_matches("0x"), 16).ok();
+    let (start, end) = (parse(start)?, parse(end)?);
+    (start < end && end <= MEM_SIZE).then_some(start..end)
+}
 
+/// Each instruction decoded linearly from the start of `buffer`, after its bytes in binary and hex
+fn debug_listing(buffer: &[u8]) -> String {
+    let mut output = String::new();
     let mut index = 0;
 
     while index < buffer.len() {
-        let first_byte = buffer[index];
-
-        let opcode;
-
-        let offset = match (first_byte >> 4) & 0b1111 { // Get first four bits
-            0b1000 => {
-                match (first_byte >> 2) & 0b111111 {
-                    0b100010 => {
-                        // MovRmToReg
-                        // 100010 D W | MOD REG R/M
-                        // if MOD == 01 (DISP-LO)
-                        // if MOD == 10 (DISP-HI)
-                        opcode = Opcode::MovRmToReg;
-                        let second_byte = buffer[index + 1];
-                        let mode = (second_byte >> 6) & 0b11;
-                        let r_m = second_byte & 0b111;
-                        match mode {
-                            0b00 => {
-                                match r_m {
-                                    0b110 => 4,
-                                    _ => 2
-                                }
-                            },
-                            0b01 => 3,
-                            0b10 => 4,
-                            0b11 => 2,
-                            _ => 0
-                        }
-                    },
-                    0b100000 => {
-                        // ImmToRm,
-                        // Could be ADD, SUB, or CMP - doesn't matter here, only length of instruction
-                        opcode = Opcode::ImmToRm;
-                        let second_byte = buffer[index + 1];
-                        let mode = (second_byte >> 6) & 0b11;
-                        let r_m = second_byte & 0b111;
-                        let s_w = first_byte & 0b11;
-                        let disp_count = match mode {
-                            0b00 => {
-                                match r_m {
-                                    0b110 => 2,
-                                    _ => 0
-                                }
-                            },
-                            0b01 => 1,
-                            0b10 => 2,
-                            0b11 => 0,
-                            _ => 0
-                        };
-
-                        // Automatically Inst/mod-000-rm/data
-                        // Maybe disp-lo/disp-hi before data
-                        // if s_w = 01 add extra data
-                        match s_w {
-                            0b00 | 0b11 => disp_count + 3,
-                            0b01 => disp_count + 4,
-                            _ => 0,
-                        }
-                    },
-                    _ => {
-                        opcode = Opcode::Unimpl;
-                        0
-                    }
-                }
-            },
-            0b1011 => {
-                // 1011 W REG | DATA
-                // if W = 1 (DATA)
-                opcode = Opcode::MovImmToReg;
-                let w = (first_byte >> 3) & 0b1;
-
-                match w {
-                    0b0 => 2,
-                    0b1 => 3,
-                    _ => 0
-                }
-            },
-            0b0000 => {
-                // ADD: Could be AddRmAndReg or AddImmToAcc
-                let first_bits = (first_byte >> 2) & 0b111111;
-
-                match first_bits {
-                    0b000000 => {
-                        opcode = Opcode::AddRmAndReg;
-                        // if MOD == 01 (DISP-LO)
-                        // if MOD == 10 (DISP-HI)
-                        let second_byte = buffer[index + 1];
-                        let mode = (second_byte >> 6) & 0b11;
-                        let r_m = second_byte & 0b111;
-                        match mode {
-                            0b00 => {
-                                match r_m {
-                                    0b110 => 4,
-                                    _ => 2
-                                }
-                            },
-                            0b01 => 3,
-                            0b10 => 4,
-                            0b11 => 2,
-                            _ => 0
-                        }
-                    },
-                    0b000001 => {
-                        opcode = Opcode::AddImmToAcc;
-                        let w = first_byte & 0b1;
-
-                        match w {
-                            0b0 => 2,
-                            0b1 => 3,
-                            _ => 0
-                        }
-                    },
-                    _ => {
-                        opcode = Opcode::Unimpl;
-                        0
-                    }
+        match decode(&buffer[index..]) {
+            Ok(instruction) => {
+                let end = index + instruction.len;
+                for byte in &buffer[index..end] {
+                    output.push_str(&format!("{:08b} ({:02X})\n", byte, byte));
                 }
-            },
-            0b0010 => {
-                // SUB: Could be SubRmAndReg or SubImmFromAcc
-                let first_bits = (first_byte >> 2) & 0b111111;
-
-                match first_bits {
-                    0b001010 => {
-                        opcode = Opcode::SubRmAndReg;
-                        // if MOD == 01 (DISP-LO)
-                        // if MOD == 10 (DISP-HI)
-                        let second_byte = buffer[index + 1];
-                        let mode = (second_byte >> 6) & 0b11;
-                        let r_m = second_byte & 0b111;
-                        match mode {
-                            0b00 => {
-                                match r_m {
-                                    0b110 => 4,
-                                    _ => 2
-                                }
-                            },
-                            0b01 => 3,
-                            0b10 => 4,
-                            0b11 => 2,
-                            _ => 0
-                        }
-                    },
-                    0b001011 => {
-                        opcode = Opcode::SubImmFromAcc;
-                        let w = first_byte & 0b1;
-
-                        match w {
-                            0b0 => 2,
-                            0b1 => 3,
-                            _ => 0
-                        }
-                    },
-                    _ => {
-                        opcode = Opcode::Unimpl;
-                        0
-                    }
-                }
-            },
-            0b0011 => {
-                // CMP: Could be CmpRmAndReg or CmpImmToAcc
-                let first_bits = (first_byte >> 2) & 0b111111;
-
-                match first_bits {
-                    0b001110 => {
-                        opcode = Opcode::CmpRmAndReg;
-                        // if MOD == 01 (DISP-LO)
-                        // if MOD == 10 (DISP-HI)
-                        let second_byte = buffer[index + 1];
-                        let mode = (second_byte >> 6) & 0b11;
-                        let r_m = second_byte & 0b111;
-                        match mode {
-                            0b00 => {
-                                match r_m {
-                                    0b110 => 4,
-                                    _ => 2
-                                }
-                            },
-                            0b01 => 3,
-                            0b10 => 4,
-                            0b11 => 2,
-                            _ => 0
-                        }
-                    },
-                    0b001111 => {
-                        opcode = Opcode::CmpImmToAcc;
-                        let w = first_byte & 0b1;
-
-                        match w {
-                            0b0 => 2,
-                            0b1 => 3,
-                            _ => 0
-                        }
-                    },
-                    _ => {
-                        opcode = Opcode::Unimpl;
-                        0
-                    },
+                if let Some(next) = buffer.get(end) {
+                    output.push_str(&format!("PEEK NEXT BYTE: {:08b} ({:02X})\n", next, next));
                 }
+                output.push_str(&format!("{}\n\n", instruction));
+                index = end;
             },
-            0b0111 | 0b1110 => {
-                // Jump or Loop
-                opcode = Opcode::from(first_byte);
-                2 // Always 2
-            },
-            _ => {
-                opcode = Opcode::Unimpl;
-                println!("SOMETHING FUCKED UP");
-                0
+            Err(err) => {
+                output.push_str(&format!("{}\n\n", DecodeError { offset: index, ..err }));
+                index += 1;
             }
-        };
-
-        if offset > 0 {
-            let instruction = Instruction::new(opcode, &buffer[index..index+offset]);
+        }
+    }
 
-            if debug {
-                for byte in &buffer[index..index + offset] {
-                    debug_output.push_str(&format!("{:08b} ({:02X})\n", byte, byte));
-                }
+    output
+}
 
-                if (index + offset) < buffer.len() {
-                    debug_output.push_str(&format!("PEEK NEXT BYTE: {:08b} ({:02X})\n", &buffer[index + offset], &buffer[index + offset]));
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                debug_output.push_str(&format!("{} {}, {}\n\n", instruction.str_val, instruction.dest, instruction.source));
-            }
+    #[test]
+    fn memdump_ranges_are_hex_with_end_exclusive() {
+        assert_eq!(parse_range("100-200"), Some(0x100..0x200));
+        assert_eq!(parse_range("0x10000-0x10100"), Some(0x10000..0x10100));
+        assert_eq!(parse_range("B8000-B8FA0"), Some(0xB8000..0xB8FA0));
+        assert_eq!(parse_range("0-100000"), Some(0..MEM_SIZE));
 
-            instructions.push(instruction);
-            index += offset;
-        } else {
-            // Break the cycle because something fucked up
-            println!("Something really fucked up");
-            index = buffer.len() + 1;
+        for bad in ["200-100", "100-100", "0-100001", "100-", "-200", "100", "", "10g-200", "100-200-300"] {
+            assert_eq!(parse_range(bad), None, "{}", bad);
         }
     }
-
-    instructions
-}
\ No newline at end of file
+}
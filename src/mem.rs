@@ -1,8 +1,8 @@
-#[allow(non_camel_case_types)]
-
 use std::fmt::{self, Formatter};
+use std::ops::Range;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Reg {
     // REG = Instruction.reg && Instruction.w
@@ -25,6 +25,23 @@ pub enum Reg {
     UNIMPL = 0b111111,
 }
 
+impl Reg {
+    /// The 16-bit register this register lives in (AL/AH -> AX, etc.)
+    pub fn full(self) -> Reg {
+        match self {
+            Self::AL | Self::AH => Self::AX,
+            Self::CL | Self::CH => Self::CX,
+            Self::DL | Self::DH => Self::DX,
+            Self::BL | Self::BH => Self::BX,
+            _ => self,
+        }
+    }
+
+    pub fn is_wide(self) -> bool {
+        (self as u8) & 0b1 != 0
+    }
+}
+
 impl From<u8> for Reg {
     fn from(value: u8) -> Self{
         match value {
@@ -49,13 +66,93 @@ impl From<u8> for Reg {
     }
 }
 
+impl FromStr for Reg {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "AL" => Ok(Self::AL),
+            "AX" => Ok(Self::AX),
+            "CX" => Ok(Self::CX),
+            "CL" => Ok(Self::CL),
+            "DX" => Ok(Self::DX),
+            "DL" => Ok(Self::DL),
+            "BX" => Ok(Self::BX),
+            "BL" => Ok(Self::BL),
+            "SP" => Ok(Self::SP),
+            "AH" => Ok(Self::AH),
+            "BP" => Ok(Self::BP),
+            "CH" => Ok(Self::CH),
+            "SI" => Ok(Self::SI),
+            "DH" => Ok(Self::DH),
+            "DI" => Ok(Self::DI),
+            "BH" => Ok(Self::BH),
+            _ => Err(()),
+        }
+    }
+}
+
 impl fmt::Display for Reg {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SegReg {
+    // SR field of MOV sreg and segment override prefixes
+    ES = 0b00,
+    CS = 0b01,
+    SS = 0b10,
+    DS = 0b11,
+}
+
+impl SegReg {
+    /// Segment override prefix: 001 SR 110
+    pub fn from_prefix(byte: u8) -> Option<SegReg> {
+        match byte {
+            0x26 => Some(Self::ES),
+            0x2E => Some(Self::CS),
+            0x36 => Some(Self::SS),
+            0x3E => Some(Self::DS),
+            _ => None,
+        }
+    }
+}
+
+impl From<u8> for SegReg {
+    fn from(value: u8) -> Self {
+        match value & 0b11 {
+            0b00 => Self::ES,
+            0b01 => Self::CS,
+            0b10 => Self::SS,
+            _ => Self::DS,
+        }
+    }
+}
+
+impl FromStr for SegReg {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "ES" => Ok(Self::ES),
+            "CS" => Ok(Self::CS),
+            "SS" => Ok(Self::SS),
+            "DS" => Ok(Self::DS),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for SegReg {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum EffectiveAddress {
     BX_SI = 0b000,
@@ -66,6 +163,7 @@ pub enum EffectiveAddress {
     DI = 0b101,
     BP = 0b110,
     BX = 0b111,
+    Direct = 0b1110, // MOD 00 with R/M 110: 16-bit displacement only
     UNIMPL = 0b11111111,
 }
 
@@ -89,15 +187,16 @@ impl fmt::Display for EffectiveAddress {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         // ImmToRm handled separately
         match self {
-            Self::BX_SI => write!(f, "{}", "BX + SI"),
-            Self::BX_DI => write!(f, "{}", "BX + DI"),
-            Self::BP_SI => write!(f, "{}", "BP + SI"),
-            Self::BP_DI => write!(f, "{}", "BP + DI"),
-            Self::SI => write!(f, "{}", "SI"),
-            Self::DI => write!(f, "{}", "DI"),
-            Self::BP => write!(f, "{}", "BP"),
-            Self::BX => write!(f, "{}", "BX"),
-            _ => write!(f, "{}", "UNIMPL")
+            Self::BX_SI => write!(f, "BX + SI"),
+            Self::BX_DI => write!(f, "BX + DI"),
+            Self::BP_SI => write!(f, "BP + SI"),
+            Self::BP_DI => write!(f, "BP + DI"),
+            Self::SI => write!(f, "SI"),
+            Self::DI => write!(f, "DI"),
+            Self::BP => write!(f, "BP"),
+            Self::BX => write!(f, "BX"),
+            Self::Direct => write!(f, "DIRECT"),
+            _ => write!(f, "UNIMPL")
         }
     }
 }
@@ -105,7 +204,7 @@ impl fmt::Display for EffectiveAddress {
 #[derive(Debug, Clone)]
 pub struct MemLoc {
     name: String,
-    value: u8,
+    value: u16,
 }
 
 impl MemLoc {
@@ -116,13 +215,99 @@ impl MemLoc {
         }
     }
 
-    pub fn write(&mut self, val: u8) {
+    pub fn write(&mut self, val: u16) {
         self.value = val;
     }
 
-    pub fn read(self) -> u8 { self.value.clone() }
+    pub fn read(&self) -> u16 { self.value }
+
+    pub fn write_lo(&mut self, val: u8) {
+        self.value = (self.value & 0xFF00) | u16::from(val);
+    }
+
+    pub fn write_hi(&mut self, val: u8) {
+        self.value = (self.value & 0x00FF) | (u16::from(val) << 8);
+    }
+
+    pub fn read_lo(&self) -> u8 { (self.value & 0xFF) as u8 }
+
+    pub fn read_hi(&self) -> u8 { (self.value >> 8) as u8 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Flag {
+    // Bit position in the FLAGS register
+    CF = 0,
+    PF = 2,
+    AF = 4,
+    ZF = 6,
+    SF = 7,
+    TF = 8,
+    IF = 9,
+    DF = 10,
+    OF = 11,
+}
+
+impl Flag {
+    pub fn list() -> [Flag; 9] {
+        [Self::CF, Self::PF, Self::AF, Self::ZF, Self::SF, Self::TF, Self::IF, Self::DF, Self::OF]
+    }
+
+    pub fn mask(self) -> u16 {
+        1 << (self as u8)
+    }
+
+    /// Single letter used when printing the set flags
+    pub fn letter(self) -> char {
+        match self {
+            Self::CF => 'C',
+            Self::PF => 'P',
+            Self::AF => 'A',
+            Self::ZF => 'Z',
+            Self::SF => 'S',
+            Self::TF => 'T',
+            Self::IF => 'I',
+            Self::DF => 'D',
+            Self::OF => 'O',
+        }
+    }
+}
+
+/// 1 MiB of addressable main memory (20-bit physical addresses)
+pub const MEM_SIZE: usize = 1 << 20;
+
+/// 20-bit physical address of segment:offset, wrapping around at the 1 MiB boundary
+pub fn physical_address(segment: u16, offset: u16) -> u32 {
+    ((u32::from(segment) << 4) + u32::from(offset)) & (MEM_SIZE as u32 - 1)
+}
+
+/// A data read or write the executor made through `read_mem`/`write_mem`, or a service through `store`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub addr: u32, // Physical address of the low byte
+    pub wide: bool,
+    pub write: bool,
+    pub emulated: bool, // Made by an emulated service, so never on the bus
+}
+
+impl Access {
+    /// Word access at an odd address, which the 8086 splits into two bus transfers
+    pub fn odd_word(&self) -> bool {
+        self.wide && self.addr & 1 == 1
+    }
+}
+
+/// Old contents of a byte an instruction overwrote, so the write can be undone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Undo {
+    Byte(u32, u8), // Physical address, old value
+    Port(u16, u8),
 }
 
+/// Number of registers in a snapshot: the general, segment and IP registers of `loc_list`, then FLAGS
+pub const REGISTER_COUNT: usize = 14;
+
 #[derive(Debug, Clone)]
 pub struct Memory {
     ax: MemLoc,
@@ -133,6 +318,22 @@ pub struct Memory {
     bp: MemLoc,
     si: MemLoc,
     di: MemLoc,
+    es: MemLoc,
+    cs: MemLoc,
+    ss: MemLoc,
+    ds: MemLoc,
+    ip: MemLoc,
+    flags: MemLoc,
+    ram: Vec<u8>,
+    ports: Vec<u8>,
+    pub accesses: Vec<Access>, // Data accesses since last cleared
+    journal: Option<Vec<Undo>>, // Overwritten bytes, while journaling
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Memory {
@@ -146,40 +347,356 @@ impl Memory {
             bp: MemLoc::new(String::from("BP")),
             si: MemLoc::new(String::from("SI")),
             di: MemLoc::new(String::from("DI")),
+            es: MemLoc::new(String::from("ES")),
+            cs: MemLoc::new(String::from("CS")),
+            ss: MemLoc::new(String::from("SS")),
+            ds: MemLoc::new(String::from("DS")),
+            ip: MemLoc::new(String::from("IP")),
+            flags: MemLoc::new(String::from("FLAGS")),
+            ram: vec![0; MEM_SIZE],
+            ports: vec![0; 1 << 16],
+            accesses: Vec::new(),
+            journal: None,
         }
     }
 
-    pub fn get_loc(&mut self, loc: &str) -> Option<&mut MemLoc> {
-        match loc {
-            "AX" => Some(&mut self.ax),
-            "CX" => Some(&mut self.cx),
-            "DX" => Some(&mut self.dx),
-            "BX" => Some(&mut self.bx),
-            "SP" => Some(&mut self.sp),
-            "BP" => Some(&mut self.bp),
-            "SI" => Some(&mut self.si),
-            "DI" => Some(&mut self.di),
+    /// Full 16-bit storage backing a register; 8-bit registers resolve to their parent.
+    pub fn get_reg(&mut self, reg: Reg) -> Option<&mut MemLoc> {
+        match reg.full() {
+            Reg::AX => Some(&mut self.ax),
+            Reg::CX => Some(&mut self.cx),
+            Reg::DX => Some(&mut self.dx),
+            Reg::BX => Some(&mut self.bx),
+            Reg::SP => Some(&mut self.sp),
+            Reg::BP => Some(&mut self.bp),
+            Reg::SI => Some(&mut self.si),
+            Reg::DI => Some(&mut self.di),
             _ => None,
         }
     }
 
+    fn reg_ref(&self, reg: Reg) -> &MemLoc {
+        match reg.full() {
+            Reg::AX => &self.ax,
+            Reg::CX => &self.cx,
+            Reg::DX => &self.dx,
+            Reg::BX => &self.bx,
+            Reg::SP => &self.sp,
+            Reg::BP => &self.bp,
+            Reg::SI => &self.si,
+            Reg::DI => &self.di,
+            _ => panic!("No storage for register {}", reg),
+        }
+    }
+
+    pub fn read_reg(&self, reg: Reg) -> u16 {
+        let loc = self.reg_ref(reg);
+        match reg {
+            Reg::AL | Reg::CL | Reg::DL | Reg::BL => u16::from(loc.read_lo()),
+            Reg::AH | Reg::CH | Reg::DH | Reg::BH => u16::from(loc.read_hi()),
+            _ => loc.read(),
+        }
+    }
+
+    pub fn write_reg(&mut self, reg: Reg, val: u16) {
+        let loc = self.get_reg(reg)
+            .unwrap_or_else(|| panic!("No storage for register {}", reg));
+        match reg {
+            Reg::AL | Reg::CL | Reg::DL | Reg::BL => loc.write_lo(val as u8),
+            Reg::AH | Reg::CH | Reg::DH | Reg::BH => loc.write_hi(val as u8),
+            _ => loc.write(val),
+        }
+    }
+
+    pub fn get_seg(&mut self, seg: SegReg) -> &mut MemLoc {
+        match seg {
+            SegReg::ES => &mut self.es,
+            SegReg::CS => &mut self.cs,
+            SegReg::SS => &mut self.ss,
+            SegReg::DS => &mut self.ds,
+        }
+    }
+
+    pub fn read_seg(&self, seg: SegReg) -> u16 {
+        match seg {
+            SegReg::ES => self.es.read(),
+            SegReg::CS => self.cs.read(),
+            SegReg::SS => self.ss.read(),
+            SegReg::DS => self.ds.read(),
+        }
+    }
+
+    pub fn write_seg(&mut self, seg: SegReg, val: u16) {
+        self.get_seg(seg).write(val);
+    }
+
+    pub fn read_ip(&self) -> u16 {
+        self.ip.read()
+    }
+
+    pub fn write_ip(&mut self, val: u16) {
+        self.ip.write(val);
+    }
+
+    pub fn read_flags(&self) -> u16 {
+        self.flags.read()
+    }
+
+    pub fn write_flags(&mut self, val: u16) {
+        self.flags.write(val);
+    }
+
+    pub fn get_flag(&self, flag: Flag) -> bool {
+        self.flags.read() & flag.mask() != 0
+    }
+
+    pub fn set_flag(&mut self, flag: Flag, val: bool) {
+        let flags = self.flags.read();
+        match val {
+            true => self.flags.write(flags | flag.mask()),
+            false => self.flags.write(flags & !flag.mask()),
+        }
+    }
+
+    /// Letters of all set flags, e.g. "CPZ"
+    pub fn flags_string(&self) -> String {
+        Flag::list().iter()
+            .filter(|flag| self.get_flag(**flag))
+            .map(|flag| flag.letter())
+            .collect()
+    }
+
+    /// Segment and offset for an effective address. BP-based addressing defaults to SS,
+    /// everything else to DS, unless a segment override prefix is given.
+    pub fn effective_address(&self, ea: EffectiveAddress, disp: u16, segment: Option<SegReg>) -> (SegReg, u16) {
+        let bx = self.read_reg(Reg::BX);
+        let bp = self.read_reg(Reg::BP);
+        let si = self.read_reg(Reg::SI);
+        let di = self.read_reg(Reg::DI);
+
+        let (base, default_seg) = match ea {
+            EffectiveAddress::BX_SI => (bx.wrapping_add(si), SegReg::DS),
+            EffectiveAddress::BX_DI => (bx.wrapping_add(di), SegReg::DS),
+            EffectiveAddress::BP_SI => (bp.wrapping_add(si), SegReg::SS),
+            EffectiveAddress::BP_DI => (bp.wrapping_add(di), SegReg::SS),
+            EffectiveAddress::SI => (si, SegReg::DS),
+            EffectiveAddress::DI => (di, SegReg::DS),
+            EffectiveAddress::BP => (bp, SegReg::SS),
+            EffectiveAddress::BX => (bx, SegReg::DS),
+            EffectiveAddress::Direct => (0, SegReg::DS),
+            EffectiveAddress::UNIMPL => panic!("Unimplemented effective address"),
+        };
+
+        (segment.unwrap_or(default_seg), base.wrapping_add(disp))
+    }
+
+    pub fn read_byte(&self, addr: u32) -> u8 {
+        self.ram[addr as usize & (MEM_SIZE - 1)]
+    }
+
+    pub fn write_byte(&mut self, addr: u32, val: u8) {
+        let addr = addr & (MEM_SIZE as u32 - 1);
+        if let Some(journal) = &mut self.journal {
+            journal.push(Undo::Byte(addr, self.ram[addr as usize]));
+        }
+        self.ram[addr as usize] = val;
+    }
+
+    /// Writes bytes at a physical address on behalf of an emulated BIOS or DOS service, logging each
+    /// one as an emulated access so watchpoints and traces see them but bus counts don't
+    pub fn store(&mut self, addr: u32, bytes: &[u8]) {
+        for (idx, byte) in bytes.iter().enumerate() {
+            let addr = (addr + idx as u32) & (MEM_SIZE as u32 - 1);
+            self.accesses.push(Access { addr, wide: false, write: true, emulated: true });
+            self.write_byte(addr, *byte);
+        }
+    }
+
+    /// Reads a byte or word at segment:offset. The high byte of a word at offset FFFF wraps to offset 0.
+    pub fn read_mem(&mut self, seg: SegReg, offset: u16, w: bool) -> u16 {
+        let segment = self.read_seg(seg);
+        self.accesses.push(Access { addr: physical_address(segment, offset), wide: w, write: false, emulated: false });
+        let lo = u16::from(self.read_byte(physical_address(segment, offset)));
+        match w {
+            true => u16::from(self.read_byte(physical_address(segment, offset.wrapping_add(1)))) << 8 | lo,
+            false => lo,
+        }
+    }
+
+    pub fn write_mem(&mut self, seg: SegReg, offset: u16, w: bool, val: u16) {
+        let segment = self.read_seg(seg);
+        self.accesses.push(Access { addr: physical_address(segment, offset), wide: w, write: true, emulated: false });
+        self.write_byte(physical_address(segment, offset), val as u8);
+        if w {
+            self.write_byte(physical_address(segment, offset.wrapping_add(1)), (val >> 8) as u8);
+        }
+    }
+
+    /// Pushes a word onto the stack at SS:SP
+    pub fn push(&mut self, val: u16) {
+        let sp = self.read_reg(Reg::SP).wrapping_sub(2);
+        self.write_reg(Reg::SP, sp);
+        self.write_mem(SegReg::SS, sp, true, val);
+    }
+
+    /// Pops a word off the stack at SS:SP
+    pub fn pop(&mut self) -> u16 {
+        let sp = self.read_reg(Reg::SP);
+        let val = self.read_mem(SegReg::SS, sp, true);
+        self.write_reg(Reg::SP, sp.wrapping_add(2));
+        val
+    }
+
+    /// Calls an interrupt handler through the vector table at 0000:0000
+    pub fn interrupt(&mut self, vector: u8) {
+        self.push(self.read_flags());
+        self.set_flag(Flag::IF, false);
+        self.set_flag(Flag::TF, false);
+        self.push(self.read_seg(SegReg::CS));
+        self.push(self.read_ip());
+
+        let (cs, ip) = self.read_vector(vector);
+        self.write_ip(ip);
+        self.write_seg(SegReg::CS, cs);
+    }
+
+    /// Reads the CS:IP of an interrupt handler from the vector table at 0000:0000
+    pub fn read_vector(&mut self, vector: u8) -> (u16, u16) {
+        let addr = u32::from(vector) * 4;
+        self.accesses.push(Access { addr, wide: true, write: false, emulated: false });
+        self.accesses.push(Access { addr: addr + 2, wide: true, write: false, emulated: false });
+        let word = |mem: &Self, addr: u32| u16::from(mem.read_byte(addr + 1)) << 8 | u16::from(mem.read_byte(addr));
+        (word(self, addr + 2), word(self, addr))
+    }
+
+    /// Reads an I/O port. Ports hold whatever was last written to them.
+    pub fn read_port(&self, port: u16, w: bool) -> u16 {
+        let lo = u16::from(self.ports[usize::from(port)]);
+        match w {
+            true => u16::from(self.ports[usize::from(port.wrapping_add(1))]) << 8 | lo,
+            false => lo,
+        }
+    }
+
+    pub fn write_port(&mut self, port: u16, w: bool, val: u16) {
+        for idx in 0..=u16::from(w) {
+            let port = port.wrapping_add(idx);
+            if let Some(journal) = &mut self.journal {
+                journal.push(Undo::Port(port, self.ports[usize::from(port)]));
+            }
+            self.ports[usize::from(port)] = (val >> (idx * 8)) as u8;
+        }
+    }
+
+    /// Starts recording the old value of every byte written to memory or a port
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stops recording and returns what was overwritten since `start_journal`
+    pub fn take_journal(&mut self) -> Vec<Undo> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Puts back bytes from a journal, newest first
+    pub fn undo(&mut self, journal: &[Undo]) {
+        for undo in journal.iter().rev() {
+            match *undo {
+                Undo::Byte(addr, val) => self.ram[addr as usize] = val,
+                Undo::Port(port, val) => self.ports[usize::from(port)] = val,
+            }
+        }
+    }
+
+    /// All registers in `loc_list` order, then FLAGS
+    pub fn registers(&self) -> [u16; REGISTER_COUNT] {
+        [
+            self.ax.read(), self.cx.read(), self.dx.read(), self.bx.read(),
+            self.sp.read(), self.bp.read(), self.si.read(), self.di.read(),
+            self.es.read(), self.cs.read(), self.ss.read(), self.ds.read(),
+            self.ip.read(), self.flags.read(),
+        ]
+    }
+
+    /// Value of a register or FLAGS in a `registers` snapshot
+    pub fn register_in(regs: &[u16; REGISTER_COUNT], loc: &str) -> Option<u16> {
+        let index = |loc: &str| Self::loc_list().iter().position(|name| *name == loc);
+        if loc == "FLAGS" {
+            return Some(regs[REGISTER_COUNT - 1]);
+        }
+        if let Some(idx) = index(loc) {
+            return Some(regs[idx]);
+        }
+
+        let reg = loc.parse::<Reg>().ok()?;
+        let full = regs[index(&reg.full().to_string())?];
+        match reg {
+            Reg::AH | Reg::CH | Reg::DH | Reg::BH => Some(full >> 8),
+            _ => Some(full & 0xFF),
+        }
+    }
+
+    pub fn set_registers(&mut self, regs: [u16; REGISTER_COUNT]) {
+        let locs = [
+            &mut self.ax, &mut self.cx, &mut self.dx, &mut self.bx,
+            &mut self.sp, &mut self.bp, &mut self.si, &mut self.di,
+            &mut self.es, &mut self.cs, &mut self.ss, &mut self.ds,
+            &mut self.ip, &mut self.flags,
+        ];
+        for (loc, val) in locs.into_iter().zip(regs) {
+            loc.write(val);
+        }
+    }
+
+    /// Reads `count` bytes starting at segment:offset, wrapping within the segment
+    pub fn fetch(&self, segment: u16, offset: u16, count: u16) -> Vec<u8> {
+        (0..count)
+            .map(|idx| self.read_byte(physical_address(segment, offset.wrapping_add(idx))))
+            .collect()
+    }
+
+    /// Copies bytes into memory starting at a physical address
+    pub fn load(&mut self, addr: u32, bytes: &[u8]) {
+        for (idx, byte) in bytes.iter().enumerate() {
+            self.write_byte(addr + idx as u32, *byte);
+        }
+    }
+
+    /// Raw bytes of a range of physical addresses
+    pub fn dump(&self, range: Range<usize>) -> &[u8] {
+        &self.ram[range]
+    }
 
-    pub fn read_loc(self, loc: &str) -> u8 {
+    pub fn get_loc(&mut self, loc: &str) -> Option<&mut MemLoc> {
+        if loc == "IP" {
+            return Some(&mut self.ip);
+        }
+        if let Ok(seg) = loc.parse::<SegReg>() {
+            return Some(self.get_seg(seg));
+        }
+
+        match loc.parse::<Reg>() {
+            Ok(reg) => self.get_reg(reg),
+            Err(_) => None,
+        }
+    }
+
+    /// Value of a register, segment register, IP or FLAGS by name, or None if there's no such register
+    pub fn read_loc(&self, loc: &str) -> Option<u16> {
         match loc {
-            "AX" => self.ax.read(),
-            "CX" => self.cx.read(),
-            "DX" => self.dx.read(),
-            "BX" => self.bx.read(),
-            "SP" => self.sp.read(),
-            "BP" => self.bp.read(),
-            "SI" => self.si.read(),
-            "DI" => self.di.read(),
-            _ => todo!(),
+            "IP" => return Some(self.ip.read()),
+            "FLAGS" => return Some(self.flags.read()),
+            _ => {},
+        }
+        if let Ok(seg) = loc.parse::<SegReg>() {
+            return Some(self.read_seg(seg));
         }
+
+        loc.parse::<Reg>().ok().map(|reg| self.read_reg(reg))
     }
 
     pub fn loc_list() -> Vec<&'static str> {
-        return vec![
+        vec![
             "AX",
             "CX",
             "DX",
@@ -188,6 +705,91 @@ impl Memory {
             "BP",
             "SI",
             "DI",
-        ];
+            "ES",
+            "CS",
+            "SS",
+            "DS",
+            "IP",
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_registers_alias_halves_of_word_registers() {
+        let mut mem = Memory::new();
+        mem.write_reg(Reg::AX, 0x1234);
+        assert_eq!(mem.read_reg(Reg::AL), 0x34);
+        assert_eq!(mem.read_reg(Reg::AH), 0x12);
+
+        mem.write_reg(Reg::AL, 0xCD);
+        assert_eq!(mem.read_reg(Reg::AX), 0x12CD);
+        mem.write_reg(Reg::AH, 0xAB);
+        assert_eq!(mem.read_reg(Reg::AX), 0xABCD);
+
+        // Only the low byte of the value goes into a byte register
+        mem.write_reg(Reg::BH, 0x1FF);
+        assert_eq!(mem.read_reg(Reg::BX), 0xFF00);
+        assert_eq!(mem.read_reg(Reg::BL), 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn byte_registers_leave_other_registers_alone() {
+        let mut mem = Memory::new();
+        for (reg, val) in [(Reg::CL, 0x11), (Reg::DH, 0x22), (Reg::BL, 0x33)] {
+            mem.write_reg(reg, val);
+        }
+        assert_eq!(mem.read_reg(Reg::AX), 0);
+        assert_eq!(mem.read_reg(Reg::CX), 0x0011);
+        assert_eq!(mem.read_reg(Reg::DX), 0x2200);
+        assert_eq!(mem.read_reg(Reg::BX), 0x0033);
+    }
+
+    #[test]
+    fn registers_by_name() {
+        let mut mem = Memory::new();
+        mem.write_reg(Reg::DX, 0x5678);
+        mem.write_seg(SegReg::SS, 0x9000);
+        assert_eq!(mem.read_loc("DH"), Some(0x56));
+        assert_eq!(mem.read_loc("SS"), Some(0x9000));
+        assert_eq!(mem.read_loc("FLAGS"), Some(0));
+        assert_eq!(mem.read_loc("XX"), None);
+        assert_eq!(Memory::register_in(&mem.registers(), "DL"), Some(0x78));
+    }
+
+    #[test]
+    fn physical_address_wraps_at_one_megabyte() {
+        assert_eq!(physical_address(0x1234, 0x5678), 0x179B8);
+        assert_eq!(physical_address(0xFFFF, 0x000F), 0xFFFFF);
+        assert_eq!(physical_address(0xFFFF, 0x0010), 0x00000);
+        assert_eq!(physical_address(0xFFFF, 0xFFFF), 0x0FFEF);
+    }
+
+    #[test]
+    fn word_at_segment_end_wraps_to_offset_zero() {
+        let mut mem = Memory::new();
+        mem.write_seg(SegReg::DS, 0x2000);
+        mem.write_mem(SegReg::DS, 0xFFFF, true, 0xBEEF);
+        assert_eq!(mem.read_byte(physical_address(0x2000, 0xFFFF)), 0xEF);
+        assert_eq!(mem.read_byte(physical_address(0x2000, 0x0000)), 0xBE);
+        assert_eq!(mem.read_mem(SegReg::DS, 0xFFFF, true), 0xBEEF);
+    }
+
+    #[test]
+    fn undo_restores_journaled_bytes_and_ports() {
+        let mut mem = Memory::new();
+        mem.write_byte(0x100, 0xAA);
+        mem.start_journal();
+        mem.write_byte(0x100, 0x11);
+        mem.write_byte(0x100, 0x22);
+        mem.write_port(0x60, true, 0x1234);
+        let journal = mem.take_journal();
+
+        mem.undo(&journal);
+        assert_eq!(mem.read_byte(0x100), 0xAA);
+        assert_eq!(mem.read_port(0x60, true), 0);
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt::{self, Formatter};
+use super::mem::*;
+
+/// Immediate value. Signed immediates are stored sign-extended to 16 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Immediate {
+    pub value: u16,
+    pub signed: bool,
+}
+
+/// Memory operand: effective address, displacement and segment override
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemOperand {
+    pub ea: EffectiveAddress,
+    pub disp: Option<u16>, // Sign-extended DISP, or the direct address
+    pub disp16: bool, // DISP was encoded as 16 bits
+    pub segment: Option<SegReg>,
+}
+
+impl MemOperand {
+    /// Segment and offset this operand refers to
+    pub fn address(&self, mem: &Memory) -> (SegReg, u16) {
+        mem.effective_address(self.ea, self.disp.unwrap_or(0), self.segment)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Seg(SegReg),
+    Imm(Immediate),
+    Mem(MemOperand),
+    Rel(i16), // Jump target relative to the start of the instruction, as in NASM's $+N
+    Far(u16, u16), // Direct far target: CS, IP
+}
+
+impl Operand {
+    /// Reads the operand. `w` selects a byte or word memory access.
+    pub fn read(&self, mem: &mut Memory, w: bool) -> u16 {
+        match self {
+            Self::Reg(reg) => mem.read_reg(*reg),
+            Self::Seg(seg) => mem.read_seg(*seg),
+            Self::Imm(imm) => imm.value,
+            Self::Mem(mem_op) => {
+                let (seg, offset) = mem_op.address(mem);
+                mem.read_mem(seg, offset, w)
+            },
+            Self::Rel(disp) => *disp as u16,
+            Self::Far(_, ip) => *ip,
+        }
+    }
+
+    /// Writes the operand. `w` selects a byte or word memory access.
+    pub fn write(&self, mem: &mut Memory, w: bool, val: u16) {
+        match self {
+            Self::Reg(reg) => mem.write_reg(*reg, val),
+            Self::Seg(seg) => mem.write_seg(*seg, val),
+            Self::Mem(mem_op) => {
+                let (seg, offset) = mem_op.address(mem);
+                mem.write_mem(seg, offset, w, val);
+            },
+            _ => panic!("Cannot write to operand {}", self),
+        }
+    }
+
+    pub fn is_mem(&self) -> bool {
+        matches!(self, Self::Mem(_))
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Reg(reg) => write!(f, "{}", reg),
+            Self::Seg(seg) => write!(f, "{}", seg),
+            Self::Imm(imm) => match imm.signed {
+                true => write!(f, "{}", imm.value as i16),
+                false => write!(f, "{}", imm.value),
+            },
+            Self::Mem(mem_op) => {
+                if let Some(seg) = mem_op.segment {
+                    write!(f, "{}:", seg)?;
+                }
+                match (mem_op.ea, mem_op.disp) {
+                    (EffectiveAddress::Direct, disp) => write!(f, "[{}]", disp.unwrap_or(0)),
+                    (ea, None) => write!(f, "[{}]", ea),
+                    (ea, Some(disp)) if (disp as i16) < 0 => write!(f, "[{} - {}]", ea, (disp as i16).unsigned_abs()),
+                    (ea, Some(disp)) => write!(f, "[{} + {}]", ea, disp),
+                }
+            },
+            Self::Rel(rel) if *rel < 0 => write!(f, "$-{}", rel.unsigned_abs()),
+            Self::Rel(rel) => write!(f, "$+{}", rel),
+            Self::Far(cs, ip) => write!(f, "{}:{}", cs, ip),
+        }
+    }
+}
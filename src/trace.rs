@@ -0,0 +1,160 @@
+use super::cpu::{Step, MAX_INST_LEN};
+use super::mem::*;
+use super::operand::Operand;
+use super::watch::{Condition, Watchpoint};
+
+/// Registers and code an instruction starts from, taken before it executes
+pub struct Before {
+    regs: [u16; REGISTER_COUNT],
+    code: Vec<u8>, // At CS:IP, in case the instruction overwrites itself
+}
+
+impl Before {
+    pub fn new(mem: &Memory) -> Self {
+        Before {
+            regs: mem.registers(),
+            code: mem.fetch(mem.read_seg(SegReg::CS), mem.read_ip(), MAX_INST_LEN),
+        }
+    }
+}
+
+/// The instruction just executed as one line of JSON, e.g.
+/// `{"cs":4096,"ip":256,"addr":65792,"bytes":"b80100","mnemonic":"MOV","size":null,"prefixes":[],"operands":["AX","1"],`
+/// `"regs":{"AX":[0,1],"IP":[256,259]},"flags":{},"writes":[],"clocks":{"base":4,"ea":0,"penalty":0,"total":4}}`.
+/// Relative jump operands are given as the target IP. Register and flag deltas are `[before, after]`
+/// for those that changed; writes hold the value written.
+pub fn json_record(before: &Before, step: &Step, mem: &Memory) -> String {
+    let Step { inst, clocks, .. } = step;
+    let locs = Memory::loc_list();
+    let index = |name: &str| locs.iter().position(|loc| *loc == name).unwrap_or(0);
+    let (cs, ip) = (before.regs[index("CS")], before.regs[index("IP")]);
+    let after = mem.registers();
+
+    let bytes: String = before.code.iter().take(inst.len).map(|byte| format!("{:02x}", byte)).collect();
+    let operands: Vec<String> = [inst.dest, inst.source].iter().flatten()
+        .map(|operand| match operand {
+            Operand::Rel(rel) => json_string(&ip.wrapping_add(*rel as u16).to_string()),
+            operand => json_string(&operand.to_string()),
+        })
+        .collect();
+
+    let regs: Vec<String> = locs.iter().zip(before.regs.iter().zip(after))
+        .filter(|(_, (pre, post))| **pre != *post)
+        .map(|(loc, (pre, post))| format!("\"{}\":[{},{}]", loc, pre, post))
+        .collect();
+
+    let (pre_flags, post_flags) = (before.regs[REGISTER_COUNT - 1], after[REGISTER_COUNT - 1]);
+    let flags: Vec<String> = Flag::list().into_iter()
+        .filter(|flag| (pre_flags ^ post_flags) & flag.mask() != 0)
+        .map(|flag| format!("\"{}F\":[{},{}]", flag.letter(), pre_flags & flag.mask() != 0, post_flags & flag.mask() != 0))
+        .collect();
+
+    let size = inst.size.map_or(String::from("null"), json_string);
+    let prefixes: Vec<String> = inst.prefixes().into_iter().map(json_string).collect();
+
+    let writes: Vec<String> = mem.accesses.iter()
+        .filter(|access| access.write)
+        .map(|access| {
+            let value = match access.wide {
+                true => u16::from_le_bytes([mem.read_byte(access.addr), mem.read_byte(access.addr + 1)]),
+                false => u16::from(mem.read_byte(access.addr)),
+            };
+            format!("{{\"addr\":{},\"size\":{},\"value\":{}}}", access.addr, if access.wide { 2 } else { 1 }, value)
+        })
+        .collect();
+
+    format!(
+        "{{\"cs\":{},\"ip\":{},\"addr\":{},\"bytes\":\"{}\",\"mnemonic\":{},\"size\":{},\"prefixes\":[{}],\"operands\":[{}],\"regs\":{{{}}},\"flags\":{{{}}},\"writes\":[{}],\
+        \"clocks\":{{\"base\":{},\"ea\":{},\"penalty\":{},\"total\":{}}}}}",
+        cs, ip, physical_address(cs, ip), bytes, json_string(&inst.mnemonic), size, prefixes.join(","), operands.join(","),
+        regs.join(","), flags.join(","), writes.join(","),
+        clocks.base, clocks.ea, clocks.penalty, clocks.total(),
+    )
+}
+
+/// Record for a trace stopped by a watchpoint, e.g.
+/// `{"stop":"watchpoint","watch":"00200-00202 w","access":"write","addr":512}`
+pub fn json_watch_stop(watchpoint: &Watchpoint, access: &Access) -> String {
+    let kind = if access.write { "write" } else { "read" };
+    format!("{{\"stop\":\"watchpoint\",\"watch\":{},\"access\":\"{}\",\"addr\":{}}}", json_string(&watchpoint.to_string()), kind, access.addr)
+}
+
+/// Record for a trace stopped by a condition, e.g. `{"stop":"condition","condition":"CX == 1"}`
+pub fn json_condition_stop(condition: &Condition) -> String {
+    format!("{{\"stop\":\"condition\",\"condition\":{}}}", json_string(&condition.to_string()))
+}
+
+/// Quoted JSON string, escaping what JSON requires
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in s.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            ch if u32::from(ch) < 0x20 => quoted.push_str(&format!("\\u{:04x}", u32::from(ch))),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    /// Records for each instruction of `program`, run as a .COM
+    fn records(program: &[u8]) -> Vec<String> {
+        let mut cpu = Cpu::new();
+        cpu.load_com(program, "");
+        let mut records = Vec::new();
+        while cpu.running() {
+            let before = Before::new(&cpu.mem);
+            let step = cpu.step().unwrap();
+            records.push(json_record(&before, &step, &cpu.mem));
+        }
+        records
+    }
+
+    #[test]
+    fn records_hold_registers_flags_and_clocks_that_changed() {
+        // MOV AX, 1; SUB AX, 2
+        let records = records(&[0xB8, 0x01, 0x00, 0x2D, 0x02, 0x00]);
+        assert_eq!(records[0], "{\"cs\":4096,\"ip\":256,\"addr\":65792,\"bytes\":\"b80100\",\"mnemonic\":\"MOV\",\"size\":null,\"prefixes\":[],\
+            \"operands\":[\"AX\",\"1\"],\"regs\":{\"AX\":[0,1],\"IP\":[256,259]},\"flags\":{},\"writes\":[],\
+            \"clocks\":{\"base\":4,\"ea\":0,\"penalty\":0,\"total\":4}}");
+        assert!(records[1].contains("\"regs\":{\"AX\":[1,65535],\"IP\":[259,262]}"), "{}", records[1]);
+        assert!(records[1].contains("\"flags\":{\"CF\":[false,true],\"PF\":[false,true],\"AF\":[false,true],\"SF\":[false,true]}"), "{}", records[1]);
+    }
+
+    #[test]
+    fn records_hold_writes_prefixes_and_jump_targets() {
+        // MOV WORD [0200h], 1234h; REP MOVSB; JMP 010Ah, the end of the program
+        let records = records(&[0xC7, 0x06, 0x00, 0x02, 0x34, 0x12, 0xF3, 0xA4, 0xEB, 0x00]);
+        assert!(records[0].contains("\"size\":\"WORD\""), "{}", records[0]);
+        assert!(records[0].contains("\"operands\":[\"[512]\",\"4660\"]"), "{}", records[0]);
+        assert!(records[0].contains("\"writes\":[{\"addr\":66048,\"size\":2,\"value\":4660}]"), "{}", records[0]);
+        assert!(records[1].contains("\"mnemonic\":\"MOVSB\",\"size\":null,\"prefixes\":[\"REP\"]"), "{}", records[1]);
+        assert!(records[2].contains("\"operands\":[\"266\"]"), "{}", records[2]);
+    }
+
+    #[test]
+    fn stop_records_name_what_stopped_the_trace() {
+        let watchpoint: Watchpoint = "200-202:rw".parse().unwrap();
+        let access = Access { addr: 0x201, wide: false, write: false, emulated: false };
+        assert_eq!(json_watch_stop(&watchpoint, &access), "{\"stop\":\"watchpoint\",\"watch\":\"00200-00202 rw\",\"access\":\"read\",\"addr\":513}");
+
+        let condition: Condition = "CX == 0 && SI != 0x10".parse().unwrap();
+        assert_eq!(json_condition_stop(&condition), "{\"stop\":\"condition\",\"condition\":\"CX == 0 && SI != 0x10\"}");
+    }
+
+    #[test]
+    fn strings_escape_quotes_backslashes_and_control_characters() {
+        assert_eq!(json_string("MOV"), "\"MOV\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("\n\r\t\u{0}\u{1f}"), "\"\\n\\u000d\\u0009\\u0000\\u001f\"");
+        assert_eq!(json_string("\u{7f}é"), "\"\u{7f}é\"");
+    }
+}
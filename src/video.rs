@@ -0,0 +1,325 @@
+use super::mem::*;
+
+/// Segment of the CGA text framebuffer
+pub const TEXT_SEGMENT: u16 = 0xB800;
+
+pub const COLS: u8 = 80;
+pub const ROWS: u8 = 25;
+
+/// Light gray on black
+const DEFAULT_ATTR: u8 = 0x07;
+
+/// 80x25 color text, the mode the BIOS boots in
+const DEFAULT_MODE: u8 = 0x03;
+
+/// Start and end scan lines of the default underline cursor
+const CURSOR_SHAPE: u16 = 0x0607;
+
+/// Code page 437 glyphs for the control characters and the upper half
+const CP437_LOW: &str = " ☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// ANSI color number for each CGA color
+const ANSI_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+/// INT 10h text-mode services over the framebuffer at B800:0000. Only display page 0 exists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Video {
+    mode: u8,
+    row: u8, // Cursor position
+    col: u8,
+}
+
+impl Default for Video {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Video {
+    pub fn new() -> Self {
+        Video {
+            mode: DEFAULT_MODE,
+            row: 0,
+            col: 0,
+        }
+    }
+
+    /// Handles INT 10h with the function in AH
+    pub fn int10(&mut self, mem: &mut Memory) {
+        let al = mem.read_reg(Reg::AL) as u8;
+        let bl = mem.read_reg(Reg::BL) as u8;
+        let bh = mem.read_reg(Reg::BH) as u8;
+        let cx = mem.read_reg(Reg::CX);
+        let (ch, cl) = ((cx >> 8) as u8, cx as u8);
+        let (dh, dl) = (mem.read_reg(Reg::DH) as u8, mem.read_reg(Reg::DL) as u8);
+
+        match mem.read_reg(Reg::AH) {
+            // Set video mode, which clears the screen
+            0x00 => {
+                self.mode = al & 0x7F;
+                self.row = 0;
+                self.col = 0;
+                clear(mem);
+            },
+            // Set cursor position
+            0x02 => {
+                self.row = dh.min(ROWS - 1);
+                self.col = dl.min(COLS - 1);
+            },
+            // Get cursor position and shape
+            0x03 => {
+                mem.write_reg(Reg::DH, u16::from(self.row));
+                mem.write_reg(Reg::DL, u16::from(self.col));
+                mem.write_reg(Reg::CX, CURSOR_SHAPE);
+            },
+            // Scroll window CH,CL..DH,DL up or down by AL lines, blanking with attribute BH. AL=0 clears it.
+            0x06 | 0x07 => scroll(mem, al, bh, (ch, cl), (dh, dl), mem.read_reg(Reg::AH) == 0x06),
+            // Write character AL with attribute BL, CX times, without moving the cursor
+            0x09 => {
+                let start = u16::from(self.row) * u16::from(COLS) + u16::from(self.col);
+                for idx in 0..cx.min(u16::from(ROWS) * u16::from(COLS) - start) {
+                    write_cell(mem, start + idx, al, Some(bl));
+                }
+            },
+            // Teletype output of AL, scrolling at the bottom of the screen
+            0x0E => self.teletype(mem, al),
+            // Get video mode, columns and page
+            0x0F => {
+                mem.write_reg(Reg::AL, u16::from(self.mode));
+                mem.write_reg(Reg::AH, u16::from(COLS));
+                mem.write_reg(Reg::BH, 0);
+            },
+            _ => {},
+        }
+    }
+
+    fn teletype(&mut self, mem: &mut Memory, ch: u8) {
+        match ch {
+            // Bell
+            0x07 => {},
+            0x08 => self.col = self.col.saturating_sub(1),
+            b'\n' => self.row += 1,
+            b'\r' => self.col = 0,
+            _ => {
+                // Keeps the attribute already on screen
+                write_cell(mem, u16::from(self.row) * u16::from(COLS) + u16::from(self.col), ch, None);
+                self.col += 1;
+                if self.col == COLS {
+                    self.col = 0;
+                    self.row += 1;
+                }
+            },
+        }
+
+        if self.row == ROWS {
+            let attr = read_cell(mem, u16::from(ROWS - 1) * u16::from(COLS)).1;
+            scroll(mem, 1, attr, (0, 0), (ROWS - 1, COLS - 1), true);
+            self.row = ROWS - 1;
+        }
+    }
+}
+
+/// Blanks the whole screen in light gray on black, as the BIOS leaves it
+pub fn clear(mem: &mut Memory) {
+    scroll(mem, 0, DEFAULT_ATTR, (0, 0), (ROWS - 1, COLS - 1), true);
+}
+
+fn cell_address(cell: u16) -> u32 {
+    physical_address(TEXT_SEGMENT, cell * 2)
+}
+
+/// Character and attribute at a cell index
+fn read_cell(mem: &Memory, cell: u16) -> (u8, u8) {
+    let addr = cell_address(cell);
+    (mem.read_byte(addr), mem.read_byte(addr + 1))
+}
+
+fn write_cell(mem: &mut Memory, cell: u16, ch: u8, attr: Option<u8>) {
+    let addr = cell_address(cell);
+    match attr {
+        Some(attr) => mem.store(addr, &[ch, attr]),
+        None => mem.store(addr, &[ch]),
+    }
+}
+
+/// Scrolls the window between `top_left` and `bottom_right` (row, col) by `lines`, filling
+/// vacated rows with blanks in `attr`. Scrolling by 0 or the window height clears it.
+fn scroll(mem: &mut Memory, lines: u8, attr: u8, top_left: (u8, u8), bottom_right: (u8, u8), up: bool) {
+    let (top, left) = top_left;
+    let (bottom, right) = (bottom_right.0.min(ROWS - 1), bottom_right.1.min(COLS - 1));
+    if top > bottom || left > right {
+        return;
+    }
+
+    let height = bottom - top + 1;
+    let lines = match lines {
+        0 => height,
+        _ => lines.min(height),
+    };
+
+    let rows: Vec<u8> = match up {
+        true => (top..=bottom).collect(),
+        false => (top..=bottom).rev().collect(),
+    };
+    for (idx, &row) in rows.iter().enumerate() {
+        // Row whose contents move into this one, if any is left
+        let source = rows.get(idx + usize::from(lines));
+        for col in left..=right {
+            let cell = u16::from(row) * u16::from(COLS) + u16::from(col);
+            let (ch, cell_attr) = match source {
+                Some(&source) => read_cell(mem, u16::from(source) * u16::from(COLS) + u16::from(col)),
+                None => (b' ', attr),
+            };
+            write_cell(mem, cell, ch, Some(cell_attr));
+        }
+    }
+}
+
+/// Unicode glyph for a code page 437 character
+fn glyph(ch: u8) -> char {
+    match ch {
+        0x00..=0x1F => CP437_LOW.chars().nth(usize::from(ch)).unwrap_or(' '),
+        0x7F => '⌂',
+        0x80..=0xFF => CP437_HIGH.chars().nth(usize::from(ch - 0x80)).unwrap_or(' '),
+        _ => char::from(ch),
+    }
+}
+
+/// The 80x25 screen as plain text, with trailing blanks trimmed from each line
+pub fn screen_text(mem: &Memory) -> String {
+    let mut output = String::new();
+    for row in 0..u16::from(ROWS) {
+        let line: String = (0..u16::from(COLS))
+            .map(|col| glyph(read_cell(mem, row * u16::from(COLS) + col).0))
+            .collect();
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+/// The 80x25 screen with each cell's attribute as ANSI colors. Blink is shown as a bright background.
+pub fn screen_ansi(mem: &Memory) -> String {
+    let mut output = String::new();
+    for row in 0..u16::from(ROWS) {
+        let mut current = None;
+        for col in 0..u16::from(COLS) {
+            let (ch, attr) = read_cell(mem, row * u16::from(COLS) + col);
+            if current != Some(attr) {
+                let fg = ANSI_COLORS[usize::from(attr & 0x07)] + if attr & 0x08 != 0 { 90 } else { 30 };
+                let bg = ANSI_COLORS[usize::from(attr >> 4 & 0x07)] + if attr & 0x80 != 0 { 100 } else { 40 };
+                output.push_str(&format!("\x1b[{};{}m", fg, bg));
+                current = Some(attr);
+            }
+            output.push(glyph(ch));
+        }
+        output.push_str("\x1b[0m\n");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen() -> (Video, Memory) {
+        let mut mem = Memory::new();
+        clear(&mut mem);
+        (Video::new(), mem)
+    }
+
+    fn call(video: &mut Video, mem: &mut Memory, ah: u8, regs: &[(Reg, u16)]) {
+        for (reg, val) in regs {
+            mem.write_reg(*reg, *val);
+        }
+        mem.write_reg(Reg::AH, u16::from(ah));
+        video.int10(mem);
+    }
+
+    fn print(video: &mut Video, mem: &mut Memory, text: &[u8]) {
+        for ch in text {
+            call(video, mem, 0x0E, &[(Reg::AL, u16::from(*ch))]);
+        }
+    }
+
+    fn cursor(video: &mut Video, mem: &mut Memory) -> (u16, u16) {
+        call(video, mem, 0x03, &[]);
+        (mem.read_reg(Reg::DH), mem.read_reg(Reg::DL))
+    }
+
+    fn line(mem: &Memory, row: usize) -> String {
+        screen_text(mem).lines().nth(row).unwrap().to_string()
+    }
+
+    #[test]
+    fn teletype_follows_backspace_carriage_return_and_line_feed() {
+        let (mut video, mut mem) = screen();
+        print(&mut video, &mut mem, b"AB\x08C\rD\x07\nE");
+        assert_eq!(line(&mem, 0), "DC");
+        assert_eq!(line(&mem, 1), " E");
+        assert_eq!(cursor(&mut video, &mut mem), (1, 2));
+
+        // Backspace stops at the left edge
+        print(&mut video, &mut mem, b"\r\x08\x08F");
+        assert_eq!(line(&mem, 1), "FE");
+    }
+
+    #[test]
+    fn teletype_wraps_lines_and_scrolls_at_the_bottom() {
+        let (mut video, mut mem) = screen();
+        print(&mut video, &mut mem, b"top\r\nsecond");
+        call(&mut video, &mut mem, 0x02, &[(Reg::DH, 24), (Reg::DL, 79)]);
+        print(&mut video, &mut mem, b"XY");
+
+        // Writing the last cell moves past row 24, so everything moves up a row
+        assert_eq!(line(&mem, 0), "second");
+        assert_eq!(line(&mem, 23), format!("{}X", " ".repeat(79)));
+        assert_eq!(line(&mem, 24), "Y");
+        assert_eq!(cursor(&mut video, &mut mem), (24, 1));
+
+        print(&mut video, &mut mem, b"\n");
+        assert_eq!(line(&mem, 0), "");
+        assert_eq!(line(&mem, 23), "Y");
+        assert_eq!(cursor(&mut video, &mut mem), (24, 1));
+    }
+
+    #[test]
+    fn scrolling_moves_only_the_window() {
+        let (mut video, mut mem) = screen();
+        print(&mut video, &mut mem, b"0abc\r\n1def\r\n2ghi\r\n3jkl");
+
+        // Rows 1-3, columns 1-2, up by one
+        call(&mut video, &mut mem, 0x06, &[(Reg::AL, 1), (Reg::BH, 0x1F), (Reg::CX, 0x0101), (Reg::DX, 0x0302)]);
+        let lines: Vec<String> = (0..4).map(|row| line(&mem, row)).collect();
+        assert_eq!(lines, ["0abc", "1ghf", "2jki", "3  l"]);
+        assert_eq!(read_cell(&mem, 3 * 80 + 1), (b' ', 0x1F));
+        assert_eq!(read_cell(&mem, 3 * 80), (b'3', DEFAULT_ATTR));
+
+        // Down by one brings back blanks at the top of the window
+        call(&mut video, &mut mem, 0x07, &[(Reg::AL, 1), (Reg::BH, 0x07), (Reg::CX, 0x0101), (Reg::DX, 0x0302)]);
+        let lines: Vec<String> = (0..4).map(|row| line(&mem, row)).collect();
+        assert_eq!(lines, ["0abc", "1  f", "2ghi", "3jkl"]);
+
+        // AL=0 clears the window
+        call(&mut video, &mut mem, 0x06, &[(Reg::AL, 0), (Reg::CX, 0x0000), (Reg::DX, 0x184F)]);
+        assert_eq!(screen_text(&mem), "\n".repeat(25));
+    }
+
+    #[test]
+    fn screens_render_code_page_437_and_colors() {
+        let (_, mut mem) = screen();
+        write_cell(&mut mem, 0, b'H', Some(0x1E));
+        write_cell(&mut mem, 1, 0x01, Some(0x8C));
+        write_cell(&mut mem, 2, 0xB0, Some(0x8C));
+
+        let text = screen_text(&mem);
+        assert_eq!(text.lines().count(), 25);
+        assert!(text.starts_with("H☺░\n\n"));
+
+        let ansi = screen_ansi(&mem);
+        let first = ansi.lines().next().unwrap();
+        assert_eq!(first, format!("\x1b[93;44mH\x1b[91;100m☺░\x1b[37;40m{}\x1b[0m", " ".repeat(77)));
+        assert_eq!(ansi.lines().nth(1).unwrap(), format!("\x1b[37;40m{}\x1b[0m", " ".repeat(80)));
+    }
+}
@@ -0,0 +1,402 @@
+use std::fmt::{self, Formatter};
+use std::str::FromStr;
+use super::mem::*;
+
+/// Stops execution when an instruction reads or writes any byte in `start..end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u32, // Physical addresses, end exclusive
+    pub end: u32,
+    pub on_read: bool,
+    pub on_write: bool,
+}
+
+impl Watchpoint {
+    /// First access of an instruction that touches the watched range
+    pub fn hit<'a>(&self, accesses: &'a [Access]) -> Option<&'a Access> {
+        accesses.iter().find(|access| {
+            let access_end = access.addr + if access.wide { 2 } else { 1 };
+            let kind = match access.write {
+                true => self.on_write,
+                false => self.on_read,
+            };
+            kind && access.addr < self.end && self.start < access_end
+        })
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let kind = match (self.on_read, self.on_write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            _ => "w",
+        };
+        write!(f, "{:05X}-{:05X} {}", self.start, self.end, kind)
+    }
+}
+
+/// `START-END[:r|w|rw]`, hex physical addresses with END exclusive. Watches writes by default.
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, kind) = s.split_once(':').unwrap_or((s, "w"));
+        let (on_read, on_write) = parse_kind(kind).ok_or(format!("Invalid watch kind {}", kind))?;
+
+        let parse = |addr: &str| u32::from_str_radix(addr.trim_start_matches("0x"), 16).ok();
+        let range = range.split_once('-').and_then(|(start, end)| Some((parse(start)?, parse(end)?)));
+        match range {
+            Some((start, end)) if start < end && end as usize <= MEM_SIZE => Ok(Watchpoint { start, end, on_read, on_write }),
+            _ => Err(format!("Invalid watch range {}: expected START-END in hex", s)),
+        }
+    }
+}
+
+/// Whether `r`, `w` or `rw` watches reads and writes
+pub fn parse_kind(kind: &str) -> Option<(bool, bool)> {
+    match kind {
+        "r" => Some((true, false)),
+        "w" => Some((false, true)),
+        "rw" => Some((true, true)),
+        _ => None,
+    }
+}
+
+/// Boolean expression over registers and flags, e.g. `CX == 0 && ZF`. Registers are any of
+/// AX..DI, AL..BH, the segment registers, IP and FLAGS; flags are CF, PF, AF, ZF, SF, TF, IF, DF
+/// and OF. Numbers are decimal or 0x hex. Anything non-zero is true.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn eval(&self, mem: &Memory) -> bool {
+        self.expr.eval(mem) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(Condition { text: s.trim().to_string(), expr }),
+            Some(token) => Err(format!("Unexpected {} in condition", token)),
+        }
+    }
+}
+
+/// A condition that fires when it becomes true, as a hardware watch condition would, rather than
+/// after every instruction for as long as it stays true
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub condition: Condition,
+    held: bool, // After the last instruction checked
+}
+
+impl Trigger {
+    /// Starts from what the condition is now, so one that already holds waits to become true again
+    pub fn new(condition: Condition, mem: &Memory) -> Self {
+        let held = condition.eval(mem);
+        Trigger { condition, held }
+    }
+
+    /// Whether the condition went from false to true since it was last checked
+    pub fn fired(&mut self, mem: &Memory) -> bool {
+        let held = std::mem::replace(&mut self.held, self.condition.eval(mem));
+        self.held && !held
+    }
+
+    /// Forgets the last state, e.g. after stepping backwards
+    pub fn reset(&mut self, mem: &Memory) {
+        self.held = self.condition.eval(mem);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(u32),
+    Name(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Num(num) => write!(f, "{}", num),
+            Self::Name(name) => write!(f, "{}", name),
+            Self::Op(op) => write!(f, "{}", op),
+            Self::Open => write!(f, "("),
+            Self::Close => write!(f, ")"),
+        }
+    }
+}
+
+/// Longer operators first so `<=` isn't read as `<`
+const OPERATORS: [&str; 9] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!"];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+
+    while let Some(ch) = rest.chars().next() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if ch == '(' || ch == ')' {
+            tokens.push(if ch == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if ch.is_ascii_alphanumeric() {
+            let len = rest.find(|ch: char| !ch.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            let word = &rest[..len];
+            let token = match (word.strip_prefix("0x"), ch.is_ascii_digit()) {
+                (Some(hex), _) => u32::from_str_radix(hex, 16).ok().map(Token::Num),
+                (None, true) => word.parse().ok().map(Token::Num),
+                (None, false) => Some(Token::Name(word.to_uppercase())),
+            };
+            tokens.push(token.ok_or(format!("Invalid number {}", word))?);
+            rest = &rest[len..];
+        } else {
+            return Err(format!("Unexpected {} in condition", ch));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Num(u32),
+    Reg(String), // Anything `Memory::read_loc` knows
+    Flag(Flag),
+    Not(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, mem: &Memory) -> u32 {
+        match self {
+            Self::Num(num) => *num,
+            // Names are checked when parsing
+            Self::Reg(name) => mem.read_loc(name).map_or(0, u32::from),
+            Self::Flag(flag) => u32::from(mem.get_flag(*flag)),
+            Self::Not(expr) => u32::from(expr.eval(mem) == 0),
+            Self::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(mem), rhs.eval(mem));
+                let result = match *op {
+                    "&&" => lhs != 0 && rhs != 0,
+                    "||" => lhs != 0 || rhs != 0,
+                    "==" => lhs == rhs,
+                    "!=" => lhs != rhs,
+                    "<" => lhs < rhs,
+                    "<=" => lhs <= rhs,
+                    ">" => lhs > rhs,
+                    _ => lhs >= rhs,
+                };
+                u32::from(result)
+            },
+        }
+    }
+}
+
+/// Recursive descent, loosest binding first: `||`, `&&`, `!`, comparisons, then values
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn next_op(&mut self, ops: &[&str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                self.pos += 1;
+                Some(op)
+            },
+            _ => None,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while let Some(op) = self.next_op(&["||"]) {
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while let Some(op) = self.next_op(&["&&"]) {
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        match self.next_op(&["!"]) {
+            Some(_) => Ok(Expr::Not(Box::new(self.not()?))),
+            None => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let expr = self.value()?;
+        match self.next_op(&["==", "!=", "<", "<=", ">", ">="]) {
+            Some(op) => Ok(Expr::Binary(op, Box::new(expr), Box::new(self.value()?))),
+            None => Ok(expr),
+        }
+    }
+
+    fn value(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.pos).ok_or("Condition ends early")?;
+        self.pos += 1;
+
+        match token {
+            Token::Num(num) => Ok(Expr::Num(*num)),
+            Token::Name(name) => {
+                if let Some(flag) = Flag::list().into_iter().find(|flag| format!("{}F", flag.letter()) == *name) {
+                    return Ok(Expr::Flag(flag));
+                }
+                let known = name == "IP" || name == "FLAGS" || name.parse::<SegReg>().is_ok() || name.parse::<Reg>().is_ok();
+                match known {
+                    true => Ok(Expr::Reg(name.clone())),
+                    false => Err(format!("Unknown register or flag {}", name)),
+                }
+            },
+            Token::Open => {
+                let expr = self.or()?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(expr)
+                    },
+                    _ => Err(String::from("Missing )")),
+                }
+            },
+            token => Err(format!("Unexpected {} in condition", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watch(start: u32, end: u32, on_read: bool, on_write: bool) -> Watchpoint {
+        Watchpoint { start, end, on_read, on_write }
+    }
+
+    fn holds(condition: &str, mem: &Memory) -> bool {
+        condition.parse::<Condition>().unwrap().eval(mem)
+    }
+
+    #[test]
+    fn watchpoints_parse_ranges_and_kinds() {
+        assert_eq!("100-200".parse(), Ok(watch(0x100, 0x200, false, true)));
+        assert_eq!("0x100-0x102:r".parse(), Ok(watch(0x100, 0x102, true, false)));
+        assert_eq!("B8000-B8FA0:rw".parse(), Ok(watch(0xB8000, 0xB8FA0, true, true)));
+        assert_eq!("FFFFF-100000".parse(), Ok(watch(0xFFFFF, 0x100000, false, true)));
+        assert_eq!(watch(0x100, 0x200, true, true).to_string(), "00100-00200 rw");
+
+        for bad in ["100", "200-100", "100-100", "100-100001", "100-20x", "-200", "100-200:x", "100-200:"] {
+            assert!(bad.parse::<Watchpoint>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn watchpoints_hit_overlapping_accesses_of_their_kind() {
+        let access = |addr, wide, write| Access { addr, wide, write, emulated: false };
+        let writes = watch(0x100, 0x102, false, true);
+        assert!(writes.hit(&[access(0x101, false, true)]).is_some());
+        // A word write ending at the first watched byte
+        assert!(writes.hit(&[access(0xFF, true, true)]).is_some());
+        assert!(writes.hit(&[access(0xFE, true, true)]).is_none());
+        assert!(writes.hit(&[access(0x102, false, true)]).is_none());
+        assert!(writes.hit(&[access(0x100, false, false)]).is_none());
+
+        let reads = watch(0x100, 0x102, true, false);
+        let accesses = [access(0x100, false, true), access(0x101, false, false)];
+        assert_eq!(reads.hit(&accesses), Some(&accesses[1]));
+    }
+
+    #[test]
+    fn conditions_compare_registers_and_flags() {
+        let mut mem = Memory::new();
+        mem.write_reg(Reg::CX, 0x10);
+        mem.set_flag(Flag::ZF, true);
+
+        assert!(holds("CX == 16", &mem));
+        assert!(holds("cx == 0x10", &mem));
+        assert!(holds("CL != 0 && CH == 0", &mem));
+        assert!(holds("ZF", &mem));
+        assert!(!holds("CF", &mem));
+        assert!(holds("CX > 15 && CX >= 16 && CX < 17 && CX <= 16", &mem));
+        assert!(holds("!CF", &mem));
+        assert!(holds("CX", &mem));
+        assert!(!holds("AX", &mem));
+        assert!(holds("IP == 0 && SS == 0 && FLAGS == 0x40", &mem));
+    }
+
+    #[test]
+    fn conditions_bind_not_tighter_than_and_tighter_than_or() {
+        let mem = Memory::new();
+        // (1 || 0) && 0 would be false
+        assert!(holds("1 || 0 && 0", &mem));
+        // !(0 || 1) would be false
+        assert!(holds("!0 || 1", &mem));
+        assert!(!holds("!(0 || 1)", &mem));
+        assert!(!holds("(1 || 0) && 0", &mem));
+        // ! applies to the whole comparison
+        assert!(holds("!AX == 1", &mem));
+        assert!(holds("!!1", &mem));
+    }
+
+    #[test]
+    fn conditions_report_syntax_errors() {
+        let error = |text: &str| text.parse::<Condition>().unwrap_err();
+        assert_eq!(error("QX == 0"), "Unknown register or flag QX");
+        assert_eq!(error("CX = 0"), "Unexpected = in condition");
+        assert_eq!(error("CX == 0x"), "Invalid number 0x");
+        assert_eq!(error("CX == 12a"), "Invalid number 12a");
+        assert_eq!(error("CX =="), "Condition ends early");
+        assert_eq!(error(""), "Condition ends early");
+        assert_eq!(error("(CX == 0"), "Missing )");
+        assert!(error("CX == 0)").starts_with("Unexpected"));
+        assert!(error("CX == 0 1").starts_with("Unexpected"));
+        assert_eq!(" CX == 0 ".parse::<Condition>().unwrap().to_string(), "CX == 0");
+    }
+
+    #[test]
+    fn triggers_fire_when_conditions_become_true() {
+        let mut mem = Memory::new();
+        let mut trigger = Trigger::new("CX == 0".parse().unwrap(), &mem);
+        // Already holding at the start
+        assert!(!trigger.fired(&mem));
+
+        mem.write_reg(Reg::CX, 1);
+        assert!(!trigger.fired(&mem));
+        mem.write_reg(Reg::CX, 0);
+        assert!(trigger.fired(&mem));
+        assert!(!trigger.fired(&mem));
+
+        mem.write_reg(Reg::CX, 1);
+        trigger.reset(&mem);
+        mem.write_reg(Reg::CX, 0);
+        assert!(trigger.fired(&mem));
+    }
+}